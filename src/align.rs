@@ -0,0 +1,387 @@
+use std::fmt;
+
+use rustfft::FftPlanner;
+
+use crate::tdoa::{resample, GccPhat};
+
+/// Clock of a recording against the clock of the reference recording: the recording reads
+/// `offset + (1 + drift) t` when the reference reads t.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ClockOffset {
+    /// s.
+    pub offset: f64,
+    /// Extra seconds per second; phones are typically tens of parts per million apart.
+    pub drift: f64,
+}
+
+impl ClockOffset {
+    /// Time on this clock when the reference reads `t`.
+    pub fn to_recording(&self, t: f64) -> f64 {
+        self.offset + (1.0 + self.drift) * t
+    }
+
+    /// Time on the reference clock when this one reads `t`.
+    pub fn to_reference(&self, t: f64) -> f64 {
+        (t - self.offset) / (1.0 + self.drift)
+    }
+}
+
+/// What the recordings are aligned on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlignmentMethod {
+    /// The strongest transient of every recording, such as a clap with the phones held
+    /// together. Gives the offset only.
+    Clap,
+    /// Cross-correlation of the energy envelopes for the rough offset, refined by GCC-PHAT in
+    /// segments along the recordings.
+    ///
+    /// The sound takes longer to the farther microphone, and that delay ends up in the offset.
+    /// A moving source changes it during the recording, which a fitted drift would take for a
+    /// clock running off.
+    #[default]
+    Envelope,
+    /// `Envelope` with a drift fitted as well; only meaningful when the microphones are close
+    /// together compared to the distance of what they hear.
+    EnvelopeDrift,
+}
+
+/// Search limits of the alignment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlignmentConfig {
+    pub method: AlignmentMethod,
+    /// Largest offset searched, s.
+    pub max_offset: f64,
+    /// Length of the segments refined with GCC-PHAT, s.
+    pub segment_duration: f64,
+    /// Step between segments, s.
+    pub segment_hop: f64,
+    /// Segments with a lower GCC-PHAT peak are left out.
+    pub min_coherence: f64,
+}
+
+impl Default for AlignmentConfig {
+    fn default() -> Self {
+        AlignmentConfig {
+            method: AlignmentMethod::default(),
+            max_offset: 30.0,
+            segment_duration: 1.0,
+            segment_hop: 2.0,
+            min_coherence: 0.05,
+        }
+    }
+}
+
+// envelope resolution, s
+const ENVELOPE_HOP: f64 = 0.005;
+// the envelope keeps changes of the level over this window, s, not the level itself
+const ENVELOPE_BASELINE: f64 = 1.0;
+// least overlap of the envelopes at any lag searched, s
+const MIN_OVERLAP: f64 = 2.0;
+// how far GCC-PHAT looks around the rough offset, s
+const REFINE_SEARCH: f64 = 0.1;
+// around the clap, s
+const CLAP_WINDOW: f64 = 0.5;
+
+/// Log energy of `signal` in `ENVELOPE_HOP` blocks, less its running mean: steady differences
+/// in gain and background between the phones drop out, onsets remain.
+pub fn envelope(signal: &[f64], rate: u32) -> Vec<f64> {
+    let block = ((ENVELOPE_HOP * rate as f64).round() as usize).max(1);
+    let energy: Vec<f64> = signal
+        .chunks(block)
+        .map(|chunk| (chunk.iter().map(|x| x * x).sum::<f64>() / chunk.len() as f64 + 1e-12).ln())
+        .collect();
+    let half = (ENVELOPE_BASELINE / ENVELOPE_HOP / 2.0).round() as usize;
+    (0..energy.len())
+        .map(|i| {
+            let window = &energy[i.saturating_sub(half)..(i + half + 1).min(energy.len())];
+            energy[i] - window.iter().sum::<f64>() / window.len() as f64
+        })
+        .collect()
+}
+
+// lag (in envelope blocks) of `other` behind `reference` whose correlation over their overlap is
+// the least likely by chance: r √n, so that a short overlap doesn't win by luck
+fn envelope_lag(reference: &[f64], other: &[f64], max_lag: usize) -> Option<isize> {
+    let min_overlap = (MIN_OVERLAP / ENVELOPE_HOP) as usize;
+    let correlation = |lag: isize| {
+        let start = lag.max(0) as usize;
+        let end = (other.len() as isize).min(reference.len() as isize + lag);
+        if end - (start as isize) < min_overlap as isize {
+            return None;
+        }
+        let pairs = (start..end as usize).map(|j| (reference[(j as isize - lag) as usize], other[j]));
+        let (mut sxy, mut sxx, mut syy, mut sx, mut sy, mut n) = (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        for (x, y) in pairs {
+            sxy += x * y;
+            sxx += x * x;
+            syy += y * y;
+            sx += x;
+            sy += y;
+            n += 1.0;
+        }
+        let covariance = sxy - sx * sy / n;
+        let spread = ((sxx - sx * sx / n) * (syy - sy * sy / n)).sqrt();
+        (spread > 0.0).then(|| covariance / spread * n.sqrt())
+    };
+    let max_lag = max_lag as isize;
+    (-max_lag..=max_lag)
+        .filter_map(|lag| correlation(lag).map(|value| (lag, value)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(lag, _)| lag)
+}
+
+// time (s) of the steepest rise of the envelope
+fn clap(envelope: &[f64]) -> Option<f64> {
+    envelope
+        .windows(2)
+        .enumerate()
+        .max_by(|a, b| (a.1[1] - a.1[0]).total_cmp(&(b.1[1] - b.1[0])))
+        .map(|(i, _)| (i + 1) as f64 * ENVELOPE_HOP)
+}
+
+/// Refines `rough` with GCC-PHAT on `length` samples of both signals around each of `times`
+/// (reference clock, s) and returns (time, offset, coherence) of the segments that correlate.
+fn refine(reference: &[f64], other: &[f64], rate: u32, rough: f64, times: &[f64], length: usize) -> Vec<(f64, f64, f64)> {
+    let rate_f = rate as f64;
+    let max_lag = (REFINE_SEARCH * rate_f).ceil() as usize;
+    let correlator = GccPhat::new(&mut FftPlanner::new(), length);
+    times
+        .iter()
+        .filter_map(|&t| {
+            let start = (t * rate_f).round() as isize - length as isize / 2;
+            let shifted = start + (rough * rate_f).round() as isize;
+            let (start, shifted) = (usize::try_from(start).ok()?, usize::try_from(shifted).ok()?);
+            let first = reference.get(start..start + length)?;
+            let second = other.get(shifted..shifted + length)?;
+            let peak = correlator.peak(first, second, max_lag)?;
+            let offset = (shifted as f64 - start as f64 + peak.lag) / rate_f;
+            Some((t, offset, peak.height))
+        })
+        .collect()
+}
+
+// weighted least-squares line offset = a + b t
+fn line(points: &[(f64, f64, f64)]) -> Option<(f64, f64)> {
+    let weights: Vec<f64> = points.iter().map(|&(_, _, coherence)| coherence * coherence).collect();
+    let total: f64 = weights.iter().sum();
+    if points.len() < 2 || total <= 0.0 {
+        return None;
+    }
+    let mean_t = points.iter().zip(&weights).map(|(p, w)| p.0 * w).sum::<f64>() / total;
+    let mean_y = points.iter().zip(&weights).map(|(p, w)| p.1 * w).sum::<f64>() / total;
+    let sxx: f64 = points.iter().zip(&weights).map(|(p, w)| w * (p.0 - mean_t).powi(2)).sum();
+    let sxy: f64 = points.iter().zip(&weights).map(|(p, w)| w * (p.0 - mean_t) * (p.1 - mean_y)).sum();
+    if sxx <= 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    Some((mean_y - slope * mean_t, slope))
+}
+
+// how far a segment may be off the line and still count, s
+const OUTLIER: f64 = 0.005;
+
+/// Estimates the clock of `other` against `reference`, both given with their sample rates.
+///
+/// `None` if nothing in the two recordings correlates within `max_offset`.
+pub fn estimate_offset(reference: (&[f64], u32), other: (&[f64], u32), config: &AlignmentConfig) -> Option<ClockOffset> {
+    let rate = reference.1.min(other.1);
+    let (reference, other) = (resample(reference.0, reference.1, rate), resample(other.0, other.1, rate));
+    let (reference_envelope, other_envelope) = (envelope(&reference, rate), envelope(&other, rate));
+
+    let rough = match config.method {
+        AlignmentMethod::Clap => clap(&other_envelope)? - clap(&reference_envelope)?,
+        AlignmentMethod::Envelope | AlignmentMethod::EnvelopeDrift => {
+            let max_lag = (config.max_offset / ENVELOPE_HOP).ceil() as usize;
+            envelope_lag(&reference_envelope, &other_envelope, max_lag)? as f64 * ENVELOPE_HOP
+        }
+    };
+    if rough.abs() > config.max_offset {
+        return None;
+    }
+
+    let duration = reference.len() as f64 / rate as f64;
+    let (times, length) = match config.method {
+        AlignmentMethod::Clap => {
+            let t = clap(&reference_envelope)?;
+            (vec![t], (CLAP_WINDOW * rate as f64) as usize)
+        }
+        AlignmentMethod::Envelope | AlignmentMethod::EnvelopeDrift => {
+            let segments = ((duration - config.segment_duration) / config.segment_hop).max(0.0) as usize + 1;
+            let times = (0..segments)
+                .map(|i| config.segment_duration / 2.0 + i as f64 * config.segment_hop)
+                .collect();
+            (times, (config.segment_duration * rate as f64) as usize)
+        }
+    };
+    let segments: Vec<(f64, f64, f64)> = refine(&reference, &other, rate, rough, &times, length)
+        .into_iter()
+        .filter(|&(_, _, coherence)| coherence >= config.min_coherence)
+        .collect();
+    if segments.is_empty() {
+        return Some(ClockOffset {
+            offset: rough,
+            drift: 0.0,
+        });
+    }
+
+    let median = {
+        let mut offsets: Vec<f64> = segments.iter().map(|&(_, offset, _)| offset).collect();
+        offsets.sort_by(f64::total_cmp);
+        offsets[offsets.len() / 2]
+    };
+    if config.method != AlignmentMethod::EnvelopeDrift {
+        // one offset for all: the segments that agree with the median, weighted by coherence
+        let agreeing: Vec<&(f64, f64, f64)> = segments.iter().filter(|s| (s.1 - median).abs() <= OUTLIER).collect();
+        let total: f64 = agreeing.iter().map(|s| s.2 * s.2).sum();
+        let offset = agreeing.iter().map(|s| s.1 * s.2 * s.2).sum::<f64>() / total;
+        return Some(ClockOffset { offset, drift: 0.0 });
+    }
+
+    // a line through the segments, then again through those close to it
+    let (mut offset, mut drift) = (median, 0.0);
+    for _ in 0..2 {
+        let inliers: Vec<(f64, f64, f64)> = segments
+            .iter()
+            .copied()
+            .filter(|&(t, segment, _)| (segment - offset - drift * t).abs() <= OUTLIER)
+            .collect();
+        match line(&inliers) {
+            Some((a, b)) => (offset, drift) = (a, b),
+            None => break,
+        }
+    }
+    Some(ClockOffset { offset, drift })
+}
+
+/// The part of a recording that overlaps the others, on the reference clock.
+///
+/// Sample i of the result is what the recording holds at `start + i / rate` on the reference
+/// clock, for `duration` seconds, interpolated linearly and zero outside the recording.
+pub fn on_reference_clock(signal: &[f64], rate: u32, clock: &ClockOffset, start: f64, duration: f64) -> Vec<f64> {
+    let rate_f = rate as f64;
+    let len = (duration * rate_f).floor().max(0.0) as usize;
+    (0..len)
+        .map(|i| {
+            let position = clock.to_recording(start + i as f64 / rate_f) * rate_f;
+            let index = position.floor();
+            let fraction = position - index;
+            if index < 0.0 {
+                return 0.0;
+            }
+            let index = index as usize;
+            match (signal.get(index), signal.get(index + 1)) {
+                (Some(a), Some(b)) => a * (1.0 - fraction) + b * fraction,
+                (Some(a), None) => *a,
+                _ => 0.0,
+            }
+        })
+        .collect()
+}
+
+/// Span (start, end) on the reference clock covered by every recording, given by its clock and
+/// duration; `None` if they don't all overlap.
+pub fn common_span(recordings: &[(ClockOffset, f64)]) -> Option<(f64, f64)> {
+    let start = recordings
+        .iter()
+        .map(|(clock, _)| clock.to_reference(0.0))
+        .fold(f64::NEG_INFINITY, f64::max);
+    let end = recordings
+        .iter()
+        .map(|(clock, duration)| clock.to_reference(*duration))
+        .fold(f64::INFINITY, f64::min);
+    (start < end).then_some((start, end))
+}
+
+/// Offsets of several files, written and read as one tab-separated line per file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OffsetTable {
+    pub entries: Vec<(String, ClockOffset)>,
+}
+
+impl OffsetTable {
+    /// Clock of `file`, as named when the table was made.
+    pub fn get(&self, file: &str) -> Option<ClockOffset> {
+        self.entries.iter().find(|(name, _)| name == file).map(|&(_, clock)| clock)
+    }
+
+    pub fn parse(text: &str) -> Result<OffsetTable, OffsetTableError> {
+        let mut entries = vec![];
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || OffsetTableError { line: index + 1 };
+            let mut fields = line.rsplitn(3, '\t');
+            let drift = fields.next().and_then(|field| field.trim().parse::<f64>().ok());
+            let offset = fields.next().and_then(|field| field.trim().parse::<f64>().ok());
+            let file = fields.next().filter(|file| !file.is_empty());
+            match (file, offset, drift) {
+                (Some(file), Some(offset), Some(ppm)) => entries.push((
+                    file.to_string(),
+                    ClockOffset {
+                        offset,
+                        drift: ppm * 1e-6,
+                    },
+                )),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(OffsetTable { entries })
+    }
+}
+
+impl fmt::Display for OffsetTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# the file reads offset + (1 + drift) t when the first one reads t")?;
+        writeln!(f, "# file\toffset (s)\tdrift (ppm)")?;
+        for (file, clock) in &self.entries {
+            writeln!(f, "{}\t{:.6}\t{:.3}", file, clock.offset, clock.drift * 1e6)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returned for a line of an offset table that isn't `file<TAB>offset<TAB>drift`.
+#[derive(Debug, Clone, Copy)]
+pub struct OffsetTableError {
+    pub line: usize,
+}
+
+impl fmt::Display for OffsetTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: expected <file>\\t<offset in s>\\t<drift in ppm>", self.line)
+    }
+}
+
+impl std::error::Error for OffsetTableError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_table_survives_a_round_trip() {
+        let table = OffsetTable {
+            entries: vec![
+                ("first.wav".to_string(), ClockOffset { offset: 0.0, drift: 0.0 }),
+                ("recordings/second take.mp3".to_string(), ClockOffset { offset: 0.7, drift: 500e-6 }),
+                ("third.wav".to_string(), ClockOffset { offset: -0.4, drift: -12.5e-6 }),
+            ],
+        };
+        let parsed = OffsetTable::parse(&table.to_string()).unwrap();
+        assert_eq!(parsed.entries.len(), table.entries.len());
+        for ((name, clock), (parsed_name, parsed_clock)) in table.entries.iter().zip(&parsed.entries) {
+            assert_eq!(name, parsed_name);
+            assert!((clock.offset - parsed_clock.offset).abs() < 1e-9);
+            assert!((clock.drift - parsed_clock.drift).abs() < 1e-12);
+        }
+        assert_eq!(parsed.get("third.wav"), Some(parsed.entries[2].1));
+    }
+
+    #[test]
+    fn offset_table_names_the_bad_line() {
+        let error = OffsetTable::parse("# comment\nfirst.wav\t0.0\t0.0\nsecond.wav\t0.7\n").unwrap_err();
+        assert_eq!(error.line, 3);
+    }
+}
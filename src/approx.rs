@@ -1,201 +1,300 @@
+use std::fmt::Display;
+
+use gomez::algo::nelder_mead::NelderMeadError;
+use gomez::algo::trust_region::TrustRegionError;
 use gomez::nalgebra as na;
-use gomez::{Domain, Function, OptimizerDriver, Problem};
+use gomez::{Domain, Function, Optimizer, OptimizerDriver, Problem};
 use na::{Dyn, IsContiguous};
 
-use crate::OneDeviceSolution;
+use crate::guess::estimate_pass;
+use crate::loader::AudioChannel;
+use crate::model::DopplerModel;
+use crate::solver::{DopplerSolver, Fit, Observations, StoppingCriteria};
+use crate::spectrum::Hz;
+use crate::tracking::TrackPoint;
+use crate::uncertainty::covariance;
+use crate::{Iterate, OneDeviceSolution, Termination};
 
-pub fn example_usage() -> () {
-    // Objective function is represented by a struct.
-    struct Rosenbrock {
-        a: f64,
-        b: f64,
-    }
+// speed of sound (T = 20 degrees Celsius)
+pub const C: f64 = 343.0;
 
-    impl Problem for Rosenbrock {
-        // Field type, f32 or f64.
-        type Field = f64;
+#[derive(Debug)]
+pub(crate) struct GetResult {
+    // lowest objective seen, not necessarily the last step
+    pub(crate) best: Iterate,
+    pub(crate) history: Vec<Iterate>,
+    pub(crate) termination: Termination,
+}
 
-        // Domain of the function.
-        fn domain(&self) -> Domain<Self::Field> {
-            Domain::unconstrained(1)
+pub(crate) struct OneDeviceProblem {
+    pub(crate) model: DopplerModel,
+    pub(crate) data: Vec<TrackPoint>,
+    pub(crate) tau0: f64,
+}
+
+impl OneDeviceProblem {
+    pub(crate) fn new(model: DopplerModel, observations: &Observations) -> Self {
+        OneDeviceProblem {
+            model,
+            data: observations.points.clone(),
+            tau0: observations.tau0,
         }
     }
 
-    impl Function for Rosenbrock {
-        // Body of the function, taking x and returning f(x).
-        fn apply<Sx>(&self, x: &na::Vector<Self::Field, Dyn, Sx>) -> Self::Field
-        where
-            Sx: na::Storage<Self::Field, Dyn> + IsContiguous,
-        {
-            self.a * x[0].powi(2) + self.b
+    // weighted least squares: every point counts by its inverse variance
+    pub(crate) fn objective(&self, values: &[f64]) -> f64 {
+        let mut values = values.to_vec();
+        self.model.limit_speed(&mut values, &self.data);
+        let values = &values[..];
+        let mut result = 0.0;
+        for y in &self.data {
+            let residual = self.model.frequency(values, self.tau0, y.time) - y.frequency.0;
+            result += (residual / y.uncertainty.0.max(f64::EPSILON)).powi(2);
+        }
+        for (_, residual, _) in self.model.priors(values) {
+            result += residual.powi(2);
         }
+        result
     }
+}
 
-    let f = Rosenbrock { a: 1.0, b: 1.0 };
-    let mut optimizer = OptimizerDriver::builder(&f)
-        .with_initial(vec![-1.0])
-        .build();
-
-    let (x, fx) = optimizer
-        .find(|state| state.fx() <= 1e-6 || state.iter() >= 15)
-        .expect("optimizer error");
-    // .unwrap();
+impl Problem for OneDeviceProblem {
+    type Field = f64;
 
-    println!("f(x) = {fx}\tx = {x:?}");
+    fn domain(&self) -> Domain<Self::Field> {
+        // Domain::unconstrained(2)
+        let (lower, upper) = self.model.domain();
+        Domain::rect(lower, upper)
+    }
 }
 
-// speed of sound (T = 20 degrees Celsius)
-pub const C: f64 = 343.0;
-
-// fn find_return(optimizer: &OptimizerDriver<'_, OneDeviceProblem, TrustRegion<OneDeviceProblem>>, initial_params: vec<f64>) {
+impl Function for OneDeviceProblem {
+    fn apply<Sx>(&self, values: &na::Vector<Self::Field, Dyn, Sx>) -> Self::Field
+    where
+        Sx: na::Storage<Self::Field, Dyn> + IsContiguous,
+    {
+        self.objective(values.as_slice())
+    }
+}
 
-// }
+/// Errors of the gomez solvers that only say the iterate can't be moved any more, which is
+/// also how they end at a minimum.
+pub(crate) trait Stall {
+    fn is_stall(&self) -> bool;
+}
 
-#[derive(Debug)]
-struct GetResult {
-    result: (Vec<f64>, f64),
-    iter: u32,
-    error: Option<gomez::algo::trust_region::TrustRegionError>,
+impl Stall for TrustRegionError {
+    // every trial step was rejected
+    fn is_stall(&self) -> bool {
+        matches!(self, TrustRegionError::NoProgress)
+    }
 }
 
-// impl GetResult {
-//     fn get_error(&self) -> Option<gomez::algo::trust_region::TrustRegionError> {
-//         gomez::algo::trust_region::TrustRegionError::
-//         self.error
-//         todo!()
-//     }
-// }
-
-// impl PartialOrd for GetResult {
-//     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-//         self.result.1.partial_cmp(&other.result.1)
-//     }
-// }
-
-// impl Ord for GetResult {
-//     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-//         self.result.1.total_cmp(&other.result.1)
-//     }
-// }
-
-pub fn one_device_approximation(data: Vec<f64>, sample_duration: f64) -> OneDeviceSolution {
-    struct OneDeviceProblem {
-        data: Vec<f64>,
-        tau0: f64,
+impl Stall for NelderMeadError {
+    fn is_stall(&self) -> bool {
+        matches!(self, NelderMeadError::SimplexCollapsed)
     }
+}
 
-    // (x0, d, v0)
-    impl Problem for OneDeviceProblem {
-        type Field = f64;
+// whether `params` is a minimum: central differences of the objective, one-sided at the
+// bounds of the domain, and a component that pushes against its bound counts as zero
+fn is_stationary(problem: &OneDeviceProblem, params: &[f64], objective: f64, tolerance: f64) -> bool {
+    let (lower, upper) = problem.model.domain();
+    let mut x = params.to_vec();
+    (0..params.len()).all(|i| {
+        let scale = params[i].abs().max(1.0);
+        let h = 1e-6 * scale;
+        let (lo, hi) = ((params[i] - h).max(lower[i]), (params[i] + h).min(upper[i]));
+        x[i] = hi;
+        let above = problem.objective(&x);
+        x[i] = lo;
+        let below = problem.objective(&x);
+        x[i] = params[i];
+        let gradient = (above - below) / (hi - lo);
+        let blocked = (params[i] <= lower[i] && gradient > 0.0) || (params[i] >= upper[i] && gradient < 0.0);
+        blocked || gradient.abs() * scale <= tolerance * objective.max(1.0)
+    })
+}
 
-        fn domain(&self) -> Domain<Self::Field> {
-            // Domain::unconstrained(2)
-            Domain::rect(vec![-100000.0, 0.0, -100000.0], vec![100000.0, 100000.0, 100000.0])
-        }
+/// Callback invoked with every iterate a solver produces.
+pub type Progress<'a> = &'a mut dyn FnMut(&Iterate);
+
+pub(crate) fn find_return<A>(
+    problem: &OneDeviceProblem,
+    optimizer: &mut OptimizerDriver<'_, OneDeviceProblem, A>,
+    stopping: &StoppingCriteria,
+    mut progress: Option<Progress>,
+) -> GetResult
+where
+    A: Optimizer<OneDeviceProblem>,
+    A::Error: Display + Stall,
+{
+    // the driver doesn't evaluate its starting point
+    let start = Iterate {
+        iteration: 0,
+        objective: problem.objective(optimizer.x()),
+        params: optimizer.x().to_vec(),
+    };
+    if let Some(progress) = progress.as_deref_mut() {
+        progress(&start);
     }
+    let mut best = start.clone();
+    let mut history = vec![start];
 
-    impl Function for OneDeviceProblem {
-        fn apply<Sx>(&self, values: &na::Vector<Self::Field, Dyn, Sx>) -> Self::Field
-        where
-            Sx: na::Storage<Self::Field, Dyn> + IsContiguous,
-        {
-            let (x0, d, v0) = (values[0], values[1], values[2]);
-            let tau0 = self.tau0;
-
-            // fn get_value(x: f64) -> f64 {
-            //     nu0
-            // }
-
-            let get_value = |t: f64| -> f64 {
-                let alpha = x0 + v0 * t + v0 * tau0;
-                let beta = x0 + v0 * t;
-                #[allow(non_snake_case)]
-                let A = (d.powi(2) + alpha.powi(2)).sqrt();
-                #[allow(non_snake_case)]
-                let B = (d.powi(2) + beta.powi(2)).sqrt();
-                let nu = C / (tau0 * C + A - B);
-                nu
-            };
-
-            let mut result = 0.0;
-            for (x, y) in self.data.iter().enumerate() {
-                result += (get_value(x as f64 * tau0) - y).powi(2);
+    let termination = loop {
+        let iteration = history.len();
+        let current = match optimizer.next() {
+            Err(e) if e.is_stall() && is_stationary(problem, &best.params, best.objective, stopping.gradient_tolerance) => {
+                break Termination::Converged(format!("{}, at a minimum", e));
             }
-            result
+            Err(e) => break Termination::Failed(e.to_string()),
+            Ok((x, fx)) => Iterate {
+                iteration,
+                params: x.to_vec(),
+                objective: fx,
+            },
+        };
+        if let Some(progress) = progress.as_deref_mut() {
+            progress(&current);
         }
-    }
+        let previous = &history[history.len() - 1].params;
+        let step = current.params.iter().zip(previous).map(|(a, b)| (a - b).powi(2)).sum::<f64>().sqrt();
+        if current.objective < best.objective {
+            best = current.clone();
+        }
+        history.push(current);
 
-    // d
-    // nu0: f64, ///// ???
-    // x0: f64,
-    // v0: f64,
-    // // not changeable
-    // tau0: f64,
+        if best.objective <= stopping.objective_threshold {
+            break Termination::ObjectiveBelowThreshold;
+        }
+        // rejected trial steps and unchanged simplex vertices don't move the iterate at all
+        if step > 0.0 && step < stopping.step_tolerance {
+            break Termination::StepBelowTolerance;
+        }
+        if iteration >= stopping.max_iterations {
+            break Termination::IterationLimit;
+        }
+    };
+    GetResult { best, history, termination }
+}
 
-    let current_problem = OneDeviceProblem {
-        data: data,
+/// Root mean square of the unweighted residuals in Hz.
+pub(crate) fn residual_rms(model: DopplerModel, observations: &Observations, params: &[f64]) -> Hz {
+    let tau0 = observations.tau0;
+    let n = observations.points.len().max(1) as f64;
+    let sum = observations
+        .points
+        .iter()
+        .map(|y| (model.frequency(params, tau0, y.time) - y.frequency.0).powi(2))
+        .sum::<f64>();
+    Hz((sum / n).sqrt())
+}
+
+/// Share of the track's spread that `params` leave unexplained: the rms residual over the
+/// standard deviation of the frequencies. Near 1 the model does no better than a steady tone.
+pub fn unexplained(model: DopplerModel, observations: &Observations, params: &[f64]) -> f64 {
+    let n = observations.points.len().max(1) as f64;
+    let mean = observations.points.iter().map(|y| y.frequency.0).sum::<f64>() / n;
+    let spread = (observations.points.iter().map(|y| (y.frequency.0 - mean).powi(2)).sum::<f64>() / n).sqrt();
+    residual_rms(model, observations, params).0 / spread
+}
+
+// closest approach of the extra starting points is moved by this fraction of the track
+const MULTI_START_SHIFT: f64 = 0.05;
+
+/// Fits the single-source `model`, starting from the pass geometry read off the track.
+///
+/// Solvers that use their starting point are run from every candidate around that estimate and
+/// the fit with the lowest objective is kept. `progress` sees the iterates of every run.
+pub fn one_device_approximation(
+    data: Vec<TrackPoint>,
+    sample_duration: f64,
+    channel: AudioChannel,
+    model: DopplerModel,
+    solver: &dyn DopplerSolver,
+    mut progress: Option<Progress>,
+) -> OneDeviceSolution {
+    let observations = Observations {
+        points: data,
         tau0: sample_duration,
     };
-    let mut optimizer = OptimizerDriver::builder(&current_problem)
-        .with_initial(vec![200.0, 40.0, 50.0])
-        .build();
-
-    fn find_return(optimizer: &mut OptimizerDriver<'_, OneDeviceProblem, gomez::algo::TrustRegion<OneDeviceProblem>>) -> GetResult {
-        let mut last_state = GetResult { result: (optimizer.x().iter().cloned().collect(), optimizer.fx()), iter: 0, error: None };
-        // let mut best_state = last_state;
-        loop {
-            println!("last_state: {:?}", last_state);
-            match optimizer.next() {
-                Err(e) => {
-                    last_state.error = Some(e);
-                    break;
-                },
-                Ok(new_result) => {
-                    if new_result.1 < last_state.result.1 || true {
-                        last_state.result = (new_result.0.iter().cloned().collect(), new_result.1);
-                        last_state.iter += 1;
-                    }
+    let candidates = match estimate_pass(&observations, model.speed_of_sound.nominal()) {
+        Some(estimate) if solver.uses_initial_guess() => {
+            let duration = observations.end() - observations.points.first().map_or(0.0, |point| point.time);
+            estimate.candidates(model, MULTI_START_SHIFT * duration)
+        }
+        Some(estimate) => vec![model.from_estimate(&estimate)],
+        None => vec![model.default_guess(&observations)],
+    };
+
+    let mut best: Option<Fit> = None;
+    for initial in candidates {
+        // reborrow for this run only
+        let progress = progress.as_mut().map(|progress| &mut **progress as Progress);
+        let fit = solver.solve(model, &observations, &initial, progress);
+        if best.as_ref().is_none_or(|best| fit.report.objective < best.report.objective) {
+            best = Some(fit);
+        }
+    }
+    // there is always at least one candidate
+    let fit = best.unwrap();
+    let uncertainty = covariance(model, &observations, &fit.params);
+    OneDeviceSolution { model, params: fit.params, tau0: sample_duration, channel, report: fit.report, uncertainty, bootstrap: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gomez::algo::NelderMead;
+
+    // a straight pass at 50 m and 40 m/s of a 300 Hz source, closest at 5 s, heard without noise
+    const TRUTH: [f64; 4] = [300.0, 50.0, 40.0, 5.0];
+
+    fn problem() -> OneDeviceProblem {
+        let model = DopplerModel::default();
+        let points = (0..100)
+            .map(|k| {
+                let time = 0.1 * k as f64;
+                TrackPoint {
+                    time,
+                    frequency: Hz(model.frequency(&TRUTH, 0.1, time)),
+                    confidence: 1.0,
+                    uncertainty: Hz(1.0),
                 }
-            }
-            // TODO: implement using traits
-            // if last_state.result.1 < best_state.result.1 {
-            //     best_state = GetResult {result: last_state.result, iter: last_state.iter, error: match last_state.error { None => None, Some(e) => Some(e)} };
-            // }
-            // conditions: TODO: implement more general
-            if last_state.result.1 < 1e-6 || last_state.iter >= 100 {
-                break;
-            }
+            })
+            .collect();
+        OneDeviceProblem::new(model, &Observations { points, tau0: 0.1 })
+    }
+
+    #[test]
+    fn best_is_the_lowest_iterate() {
+        let problem = problem();
+        // from this guess the trust region fails far above its best point, and Nelder-Mead runs
+        // out of iterations
+        let stopping = StoppingCriteria::default();
+        let mut trust_region = OptimizerDriver::builder(&problem).with_initial(vec![290.0, 70.0, 30.0, 4.0]).build();
+        let mut nelder_mead = OptimizerDriver::builder(&problem)
+            .with_algo(NelderMead::new)
+            .with_initial(vec![290.0, 70.0, 30.0, 4.0])
+            .build();
+        let results = [
+            find_return(&problem, &mut trust_region, &stopping, None),
+            find_return(&problem, &mut nelder_mead, &stopping, None),
+        ];
+        assert!(results[0].history.last().unwrap().objective > results[0].best.objective);
+        for result in results {
+            let lowest = result.history.iter().min_by(|a, b| a.objective.total_cmp(&b.objective)).unwrap();
+            assert_eq!(result.best.objective, lowest.objective);
+            assert_eq!(result.best.params, lowest.params);
+            assert_eq!(problem.objective(&result.best.params), result.best.objective);
         }
-        last_state
     }
 
-    /*let result = optimizer
-        .find(|state| {
-            println!(
-                "it={} fx={} [x0, v0]={:?}",
-                state.iter(),
-                state.fx(),
-                state.x()
-            );
-            // last_x = state.x();
-            // last_fx = state.fx();Z
-            state.fx() <= 1e-6 || state.iter() >= 100
-        });
-        // .expect("optimizer error")
-        // .inspect_err(|e| eprintln!("error: {e}"))
-    */
-    
-    // if let Err(e) = result {
-    //     eprintln!("error: {}", e);
-    // } else {
-    //     let (x, fx) = result.unwrap();
-    //     println!("found best : {} at {:?}", fx, x);
-    // }
-
-    let result = find_return(&mut optimizer);
-    println!("final result: {:?}", result);
-
-    println!("{}", optimizer.name());
-
-    OneDeviceSolution { x0: result.result.0[0], d: result.result.0[1], v0: result.result.0[2], tau0: sample_duration }
+    #[test]
+    fn stalls_converge_only_at_a_minimum() {
+        let problem = problem();
+        assert!(is_stationary(&problem, &TRUTH, problem.objective(&TRUTH), 1e-4));
+        let off = [300.0, 55.0, 40.0, 5.0];
+        assert!(!is_stationary(&problem, &off, problem.objective(&off), 1e-4));
+    }
 }
@@ -0,0 +1,185 @@
+use crate::spectrum::Spectrogram;
+
+/// How the per-bin noise floor is estimated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseEstimator {
+    /// Median magnitude of every bin over the whole recording. Assumes stationary noise and a
+    /// tone that occupies any one bin for less than half of the time.
+    Median,
+    /// Minimum statistics (Martin): the minimum of the recursively smoothed power over a sliding
+    /// window of the given length in seconds, corrected for the bias of taking a minimum.
+    /// Follows slowly changing noise such as wind.
+    MinimumStatistics { window: f64 },
+}
+
+/// What is done with the spectrum once the noise floor is known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseReduction {
+    /// Power spectral subtraction: |X|² - over_subtraction * N², never below floor * |X|².
+    Subtraction { over_subtraction: f64, floor: f64 },
+    /// Wiener gain SNR / (1 + SNR) with the SNR estimated from the noise floor, never below
+    /// `min_gain`.
+    Wiener { min_gain: f64 },
+    /// Divides every bin by its noise floor, so magnitudes become signal-to-noise ratios.
+    Whitening,
+}
+
+// smoothing constant of the power recursion in minimum statistics
+const MIN_STATS_ALPHA: f64 = 0.85;
+// rough compensation of the minimum's downward bias for that smoothing
+const MIN_STATS_BIAS: f64 = 1.5;
+
+/// Noise floor magnitude `floor[frame][bin]`, on the same scale as `spectrogram.magnitudes`.
+pub fn noise_floor(spectrogram: &Spectrogram, estimator: NoiseEstimator) -> Vec<Vec<f64>> {
+    let frames = spectrogram.len();
+    if frames == 0 {
+        return vec![];
+    }
+    let bins = spectrogram.magnitudes[0].len();
+
+    match estimator {
+        NoiseEstimator::Median => {
+            let mut median = vec![0.0; bins];
+            let mut column = vec![0.0; frames];
+            for (bin, value) in median.iter_mut().enumerate() {
+                for (frame, spectrum) in spectrogram.magnitudes.iter().enumerate() {
+                    column[frame] = spectrum[bin];
+                }
+                column.sort_by(f64::total_cmp);
+                *value = column[frames / 2];
+            }
+            vec![median; frames]
+        }
+        NoiseEstimator::MinimumStatistics { window } => {
+            let span = ((window / spectrogram.hop_duration).round() as usize).max(1);
+            let mut floor = vec![vec![0.0; bins]; frames];
+            for bin in 0..bins {
+                let mut smoothed = Vec::with_capacity(frames);
+                let mut power = spectrogram.magnitudes[0][bin].powi(2);
+                for spectrum in &spectrogram.magnitudes {
+                    power = MIN_STATS_ALPHA * power + (1.0 - MIN_STATS_ALPHA) * spectrum[bin].powi(2);
+                    smoothed.push(power);
+                }
+                for (frame, row) in floor.iter_mut().enumerate() {
+                    // window centred on the frame, so the start of the file isn't special
+                    let lo = frame.saturating_sub(span / 2);
+                    let hi = (lo + span).min(frames);
+                    let minimum = smoothed[lo..hi].iter().cloned().fold(f64::INFINITY, f64::min);
+                    row[bin] = (MIN_STATS_BIAS * minimum).sqrt();
+                }
+            }
+            floor
+        }
+    }
+}
+
+/// Estimates the noise floor and suppresses it in place.
+///
+/// Complex spectra, if still present, get the same per-bin gain as the magnitudes, so the
+/// phase-based peak estimators keep working.
+pub fn denoise(spectrogram: &mut Spectrogram, estimator: NoiseEstimator, reduction: NoiseReduction) {
+    let floor = noise_floor(spectrogram, estimator);
+    for (frame, noise) in floor.iter().enumerate() {
+        for (bin, n) in noise.iter().enumerate() {
+            let magnitude = spectrogram.magnitudes[frame][bin];
+            let gain = gain(magnitude, *n, reduction);
+            spectrogram.magnitudes[frame][bin] = magnitude * gain;
+            if let Some(spectrum) = spectrogram.spectra.get_mut(frame) {
+                spectrum[bin] *= gain;
+            }
+        }
+    }
+}
+
+// amplitude gain for one bin
+fn gain(magnitude: f64, noise: f64, reduction: NoiseReduction) -> f64 {
+    if magnitude <= 0.0 {
+        return 0.0;
+    }
+    let power = magnitude * magnitude;
+    let noise_power = noise * noise;
+    match reduction {
+        NoiseReduction::Subtraction {
+            over_subtraction,
+            floor,
+        } => ((power - over_subtraction * noise_power).max(floor * power) / power).sqrt(),
+        NoiseReduction::Wiener { min_gain } => {
+            if noise_power <= 0.0 {
+                return 1.0;
+            }
+            let snr = (power / noise_power - 1.0).max(0.0);
+            (snr / (1.0 + snr)).max(min_gain)
+        }
+        NoiseReduction::Whitening => {
+            if noise > 0.0 {
+                1.0 / noise
+            } else {
+                1.0
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 40 frames of 64 bins: Rayleigh noise of RMS 0.1, from a linear congruential generator,
+    // and a tone of 1 climbing one bin per frame, so no bin holds it for long
+    fn noisy_tone() -> Spectrogram {
+        let mut state = 7u64;
+        let mut uniform = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 11) as f64 + 1.0) / (1u64 << 53) as f64
+        };
+        let magnitudes = (0..40)
+            .map(|frame| {
+                (0..64)
+                    .map(|bin| if bin == 10 + frame { 1.0 } else { 0.1 * (-uniform().ln()).sqrt() })
+                    .collect()
+            })
+            .collect();
+        Spectrogram {
+            magnitudes,
+            spectra: vec![],
+            times: (0..40).map(|frame| 0.016 + 0.032 * frame as f64).collect(),
+            hop_duration: 0.032,
+            sample_rate: 8000,
+            fft_len: 126,
+        }
+    }
+
+    fn mean_floor(floor: &[Vec<f64>]) -> f64 {
+        floor.iter().flatten().sum::<f64>() / floor.iter().map(|row| row.len()).sum::<usize>() as f64
+    }
+
+    #[test]
+    fn noise_floors_find_the_noise() {
+        let spectrogram = noisy_tone();
+        // the median of a Rayleigh magnitude is sqrt(ln 2) of its RMS
+        let median = mean_floor(&noise_floor(&spectrogram, NoiseEstimator::Median));
+        assert!((median - 0.1 * 2f64.ln().sqrt()).abs() < 0.01, "{}", median);
+        let minimum = mean_floor(&noise_floor(&spectrogram, NoiseEstimator::MinimumStatistics { window: 0.5 }));
+        assert!((minimum - 0.1).abs() < 0.03, "{}", minimum);
+    }
+
+    #[test]
+    fn reduction_keeps_the_tone_on_top() {
+        let reductions = [
+            NoiseReduction::Subtraction {
+                over_subtraction: 2.0,
+                floor: 0.01,
+            },
+            NoiseReduction::Wiener { min_gain: 0.05 },
+            NoiseReduction::Whitening,
+        ];
+        for reduction in reductions {
+            let mut spectrogram = noisy_tone();
+            denoise(&mut spectrogram, NoiseEstimator::Median, reduction);
+            for (frame, distribution) in spectrogram.magnitudes.iter().enumerate() {
+                let peak = (0..distribution.len()).max_by(|a, b| distribution[*a].total_cmp(&distribution[*b])).unwrap();
+                assert_eq!(peak, 10 + frame, "{:?}", reduction);
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::f64::consts::PI;
+
+use crate::spectrum::Hz;
+
+/// Second-order IIR section (RBJ audio EQ cookbook), normalised so that a0 = 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    fn from_coefficients(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+        }
+    }
+
+    // (cos w0, alpha) of the cookbook formulas
+    fn prewarp(sample_rate: u32, frequency: Hz, q: f64) -> (f64, f64) {
+        let w0 = 2.0 * PI * frequency.0 / sample_rate as f64;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    /// Low-pass; `cutoff` must lie between 0 and Nyquist, beyond which the section is unstable.
+    pub fn lowpass(sample_rate: u32, cutoff: Hz, q: f64) -> Self {
+        assert!(below_nyquist(sample_rate, cutoff), "cutoff must lie between 0 and Nyquist");
+        let (cos, alpha) = Self::prewarp(sample_rate, cutoff, q);
+        Self::from_coefficients(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// High-pass; `cutoff` must lie between 0 and Nyquist, as for `lowpass`.
+    pub fn highpass(sample_rate: u32, cutoff: Hz, q: f64) -> Self {
+        assert!(below_nyquist(sample_rate, cutoff), "cutoff must lie between 0 and Nyquist");
+        let (cos, alpha) = Self::prewarp(sample_rate, cutoff, q);
+        Self::from_coefficients(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Band-pass with 0 dB gain at `centre`.
+    pub fn bandpass(sample_rate: u32, centre: Hz, q: f64) -> Self {
+        let (cos, alpha) = Self::prewarp(sample_rate, centre, q);
+        Self::from_coefficients(
+            [alpha, 0.0, -alpha],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Band-stop; higher `q` gives a narrower notch.
+    pub fn notch(sample_rate: u32, centre: Hz, q: f64) -> Self {
+        let (cos, alpha) = Self::prewarp(sample_rate, centre, q);
+        Self::from_coefficients(
+            [1.0, -2.0 * cos, 1.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Filters the signal in place (transposed direct form II).
+    pub fn process(&self, signal: &mut [f64]) {
+        let (mut z1, mut z2) = (0.0, 0.0);
+        for sample in signal.iter_mut() {
+            let x = *sample;
+            let y = self.b0 * x + z1;
+            z1 = self.b1 * x - self.a1 * y + z2;
+            z2 = self.b2 * x - self.a2 * y;
+            *sample = y;
+        }
+    }
+}
+
+/// Whether `frequency` lies strictly between 0 and half the sample rate, where the low- and
+/// high-pass sections can be built.
+pub fn below_nyquist(sample_rate: u32, frequency: Hz) -> bool {
+    frequency.0 > 0.0 && frequency.0 < sample_rate as f64 / 2.0
+}
+
+// Q of each biquad of an even-order Butterworth filter
+fn butterworth_q(order: usize) -> impl Iterator<Item = f64> {
+    let order = order.max(2) & !1;
+    (0..order / 2).map(move |k| {
+        let theta = PI * (2 * k + 1) as f64 / (2 * order) as f64;
+        1.0 / (2.0 * theta.cos())
+    })
+}
+
+/// Cascade of biquads applied to the time-domain signal before the STFT.
+#[derive(Debug, Clone, Default)]
+pub struct FilterChain {
+    sections: Vec<Biquad>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    pub fn push(mut self, section: Biquad) -> Self {
+        self.sections.push(section);
+        self
+    }
+
+    /// Butterworth high-pass; odd orders are rounded down to the next even one.
+    pub fn highpass(mut self, sample_rate: u32, cutoff: Hz, order: usize) -> Self {
+        for q in butterworth_q(order) {
+            self.sections.push(Biquad::highpass(sample_rate, cutoff, q));
+        }
+        self
+    }
+
+    /// Butterworth low-pass; odd orders are rounded down to the next even one.
+    pub fn lowpass(mut self, sample_rate: u32, cutoff: Hz, order: usize) -> Self {
+        for q in butterworth_q(order) {
+            self.sections.push(Biquad::lowpass(sample_rate, cutoff, q));
+        }
+        self
+    }
+
+    /// Band-pass built from a high-pass at `lo` and a low-pass at `hi`.
+    pub fn bandpass(self, sample_rate: u32, lo: Hz, hi: Hz, order: usize) -> Self {
+        self.highpass(sample_rate, lo, order)
+            .lowpass(sample_rate, hi, order)
+    }
+
+    /// Notches at `fundamental` and its first `harmonics - 1` overtones below Nyquist, e.g. for
+    /// 50/60 Hz mains hum.
+    pub fn notch(mut self, sample_rate: u32, fundamental: Hz, q: f64, harmonics: usize) -> Self {
+        let nyquist = sample_rate as f64 / 2.0;
+        for h in 1..=harmonics {
+            let centre = fundamental.0 * h as f64;
+            if centre >= nyquist {
+                break;
+            }
+            self.sections.push(Biquad::notch(sample_rate, Hz(centre), q));
+        }
+        self
+    }
+
+    /// Runs every section over the signal in place.
+    pub fn apply(&self, signal: &mut [f64]) {
+        for section in &self.sections {
+            section.process(signal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // amplitude a unit sine at `frequency` comes out with, once the filter has settled
+    fn gain(filters: &FilterChain, frequency: f64) -> f64 {
+        let mut signal: Vec<f64> = (0..16000).map(|i| (2.0 * PI * frequency * i as f64 / 8000.0).sin()).collect();
+        filters.apply(&mut signal);
+        let settled = &signal[8000..];
+        (2.0 * settled.iter().map(|x| x * x).sum::<f64>() / settled.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn butterworth_is_3_db_down_at_the_cutoff() {
+        let lowpass = FilterChain::new().lowpass(8000, Hz(1000.0), 4);
+        assert!((gain(&lowpass, 250.0) - 1.0).abs() < 0.01);
+        assert!((gain(&lowpass, 1000.0) - 0.5f64.sqrt()).abs() < 0.01);
+        assert!(gain(&lowpass, 3000.0) < 0.01);
+
+        let highpass = FilterChain::new().highpass(8000, Hz(1000.0), 4);
+        assert!(gain(&highpass, 250.0) < 0.01);
+        assert!((gain(&highpass, 1000.0) - 0.5f64.sqrt()).abs() < 0.01);
+        assert!((gain(&highpass, 3000.0) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn notch_removes_the_hum_and_its_overtones() {
+        let notch = FilterChain::new().notch(8000, Hz(50.0), 30.0, 5);
+        assert!(gain(&notch, 50.0) < 0.01);
+        assert!(gain(&notch, 150.0) < 0.01);
+        assert!((gain(&notch, 400.0) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn cutoffs_stay_below_nyquist() {
+        assert!(below_nyquist(8000, Hz(3999.0)));
+        assert!(!below_nyquist(8000, Hz(4000.0)));
+        assert!(!below_nyquist(8000, Hz(0.0)));
+    }
+}
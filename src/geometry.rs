@@ -0,0 +1,39 @@
+/// Side of the track the microphone is on, looking in the direction of flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Side {
+    Left,
+    #[default]
+    Right,
+}
+
+/// How the track lies in the local east/north/up frame centred on the microphone.
+///
+/// Neither the heading nor the side changes what a single microphone hears, so they can't be
+/// fitted from one track; knowing them only places the fitted pass on the map.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Orientation {
+    /// Direction of flight at closest approach, degrees clockwise from north.
+    pub heading: f64,
+    pub side: Side,
+}
+
+impl Orientation {
+    /// East/north/up coordinates (m) of a point given in the track frame: x along the track at
+    /// closest approach, y across it (towards the track, seen from the microphone), z up.
+    pub fn to_enu(&self, position: [f64; 3]) -> [f64; 3] {
+        let (sin, cos) = self.heading.to_radians().sin_cos();
+        let forward = [sin, cos];
+        // the track frame is right-handed with z up, so +y is to the left of the flight
+        // direction; the track lies there when the microphone is on its right
+        let left = [-cos, sin];
+        let across = match self.side {
+            Side::Right => position[1],
+            Side::Left => -position[1],
+        };
+        [
+            position[0] * forward[0] + across * left[0],
+            position[0] * forward[1] + across * left[1],
+            position[2],
+        ]
+    }
+}
@@ -0,0 +1,125 @@
+use crate::model::DopplerModel;
+use crate::solver::Observations;
+use crate::tracking::merge_simultaneous;
+
+/// Pass geometry read off the shape of a frequency track, used to start the fit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PassEstimate {
+    /// Time of closest approach (s), where the track falls the steepest.
+    pub t_closest: f64,
+    /// Frequency heard while the source approaches and recedes.
+    pub f_approach: f64,
+    pub f_recede: f64,
+    /// Speed (m/s) from the Doppler ratio of the two plateaus.
+    pub speed: f64,
+    /// Frequency the source emits, halfway between the plateaus in period.
+    pub source_frequency: f64,
+    /// Miss distance (m) from the slope at closest approach.
+    pub distance: f64,
+}
+
+// frames of the median filter run over the track before differentiating
+const MEDIAN_FRAMES: usize = 5;
+// fraction of either side of the pass whose median is taken as its plateau
+const PLATEAU_FRACTION: f64 = 0.25;
+
+impl PassEstimate {
+    /// Starting points for a multi-start fit of `model`: the estimate itself first, then every
+    /// combination of half/double distance, ±20 % speed and closest approach moved by
+    /// `time_shift` seconds.
+    pub fn candidates(&self, model: DopplerModel, time_shift: f64) -> Vec<Vec<f64>> {
+        let mut candidates = vec![model.from_estimate(self)];
+        for distance_scale in [0.5, 1.0, 2.0] {
+            for speed_scale in [0.8, 1.0, 1.25] {
+                for shift in [-time_shift, 0.0, time_shift] {
+                    if distance_scale == 1.0 && speed_scale == 1.0 && shift == 0.0 {
+                        continue;
+                    }
+                    let shifted = PassEstimate {
+                        t_closest: self.t_closest + shift,
+                        speed: self.speed * speed_scale,
+                        distance: self.distance * distance_scale,
+                        ..*self
+                    };
+                    candidates.push(model.from_estimate(&shifted));
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// Reads the pass geometry off a track, assuming sound travels at `c` m/s.
+///
+/// Returns `None` when the track is too short or does not fall, i.e. shows no pass. Points
+/// that share a time are merged first, see `merge_simultaneous`.
+pub fn estimate_pass(observations: &Observations, c: f64) -> Option<PassEstimate> {
+    let points = merge_simultaneous(&observations.points);
+    let n = points.len();
+    if n < 3 * MEDIAN_FRAMES {
+        return None;
+    }
+
+    // median filter against single-frame jumps to another peak
+    let raw: Vec<f64> = points.iter().map(|p| p.frequency.0).collect();
+    let half = MEDIAN_FRAMES / 2;
+    let track: Vec<f64> = (0..n)
+        .map(|i| {
+            let mut window = raw[i.saturating_sub(half)..(i + half + 1).min(n)].to_vec();
+            window.sort_by(f64::total_cmp);
+            window[window.len() / 2]
+        })
+        .collect();
+
+    // least-squares slope over a window of about 5 % of the track
+    let span = (n / 20).max(MEDIAN_FRAMES) / 2;
+    let times: Vec<f64> = points.iter().map(|p| p.time).collect();
+    let slope_at = |i: usize| {
+        let (lo, hi) = (i.saturating_sub(span), (i + span + 1).min(n));
+        let count = (hi - lo) as f64;
+        let mean_x = times[lo..hi].iter().sum::<f64>() / count;
+        let mean_y = track[lo..hi].iter().sum::<f64>() / count;
+        let (mut sxy, mut sxx) = (0.0, 0.0);
+        for (x, y) in times[lo..hi].iter().zip(&track[lo..hi]) {
+            sxy += (x - mean_x) * (y - mean_y);
+            sxx += (x - mean_x).powi(2);
+        }
+        sxy / sxx
+    };
+
+    // the inflection point of a pass is where the frequency falls the fastest
+    let (closest, slope) = (0..n)
+        .map(|i| (i, slope_at(i)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))?;
+    if slope >= 0.0 {
+        return None;
+    }
+
+    let plateau = |values: &[f64]| {
+        let mut values = values.to_vec();
+        values.sort_by(f64::total_cmp);
+        values[values.len() / 2]
+    };
+    let approach_len = ((closest as f64 * PLATEAU_FRACTION) as usize).max(1);
+    let recede_len = (((n - closest) as f64 * PLATEAU_FRACTION) as usize).max(1);
+    let f_approach = plateau(&track[..approach_len]);
+    let f_recede = plateau(&track[n - recede_len..]);
+    if f_approach <= f_recede {
+        return None;
+    }
+
+    // f_approach = f0 c / (c - v), f_recede = f0 c / (c + v)
+    let speed = c * (f_approach - f_recede) / (f_approach + f_recede);
+    let source_frequency = 2.0 * f_approach * f_recede / (f_approach + f_recede);
+    // at closest approach the radial speed is zero and its rate v^2 / d, so df/dt = -f0 v^2 / (c d)
+    let distance = source_frequency * speed.powi(2) / (c * slope.abs());
+
+    Some(PassEstimate {
+        t_closest: times[closest],
+        f_approach,
+        f_recede,
+        speed,
+        source_frequency,
+        distance,
+    })
+}
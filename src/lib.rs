@@ -0,0 +1,199 @@
+pub mod align;
+pub mod approx;
+pub mod denoise;
+pub mod filter;
+pub mod geometry;
+pub mod guess;
+pub mod lma;
+pub mod loader;
+pub mod model;
+pub mod multi;
+pub mod plot;
+pub mod selection;
+pub mod smoothing;
+pub mod solver;
+pub mod sound;
+pub mod spectrum;
+pub mod tdoa;
+pub mod tracking;
+pub mod trajectory;
+pub mod uncertainty;
+
+use std::fmt;
+
+use geometry::Orientation;
+use loader::AudioChannel;
+use model::DopplerModel;
+use solver::{DopplerSolver, Observations};
+use spectrum::Hz;
+use uncertainty::{Bootstrap, ParameterUncertainty};
+
+pub struct OneDeviceSolution {
+    model: DopplerModel,
+    // in the order of `model.parameter_names()`
+    params: Vec<f64>,
+    tau0: f64,
+    // which signal of the recording the frequencies were taken from
+    channel: AudioChannel,
+    report: FitReport,
+    uncertainty: Option<ParameterUncertainty>,
+    bootstrap: Option<Bootstrap>,
+}
+
+impl OneDeviceSolution {
+    pub fn channel(&self) -> AudioChannel {
+        self.channel
+    }
+
+    pub fn model(&self) -> DopplerModel {
+        self.model
+    }
+
+    /// Fitted parameters, named by `model().parameter_names()`.
+    pub fn params(&self) -> &[f64] {
+        &self.params
+    }
+
+    /// Position of the source along its track at t = 0 (m).
+    pub fn x0(&self) -> f64 {
+        self.model.x0(&self.params)
+    }
+
+    /// Time of closest approach (s).
+    pub fn closest_approach(&self) -> f64 {
+        self.model.closest_approach(&self.params)
+    }
+
+    /// Position of the source at time t (m): x along the track at closest approach, y across
+    /// it, z up, relative to the microphone.
+    pub fn position(&self, t: f64) -> [f64; 3] {
+        self.model.position(&self.params, t)
+    }
+
+    /// Position of the source at time t (m) east, north and up of the microphone.
+    pub fn enu_position(&self, orientation: &Orientation, t: f64) -> [f64; 3] {
+        orientation.to_enu(self.position(t))
+    }
+
+    /// Miss distance (m).
+    pub fn distance(&self) -> f64 {
+        self.model.distance(&self.params)
+    }
+
+    /// Ground speed of the source (m/s).
+    pub fn speed(&self) -> f64 {
+        self.params[self.model.speed_index()]
+    }
+
+    /// Speed of the source relative to the air (m/s).
+    pub fn airspeed(&self) -> f64 {
+        self.model.airspeed(&self.params)
+    }
+
+    /// Wind along the track the fit used (m/s), fixed or fitted.
+    pub fn wind(&self) -> f64 {
+        self.model.wind(&self.params)
+    }
+
+    /// Speed of sound the fit used (m/s), fixed or fitted.
+    pub fn speed_of_sound(&self) -> f64 {
+        self.model.speed_of_sound(&self.params)
+    }
+
+    /// Rest frequency of the source, if the model fits one.
+    pub fn source_frequency(&self) -> Option<Hz> {
+        self.model.source_frequency(&self.params).map(Hz)
+    }
+
+    /// Heard frequency the fit predicts at time t.
+    pub fn frequency(&self, t: f64) -> Hz {
+        Hz(self.model.frequency(&self.params, self.tau0, t))
+    }
+
+    pub fn report(&self) -> &FitReport {
+        &self.report
+    }
+
+    /// Error bars of `params`; `None` when the fit is degenerate.
+    pub fn uncertainty(&self) -> Option<&ParameterUncertainty> {
+        self.uncertainty.as_ref()
+    }
+
+    /// Standard errors from refitting resampled frames, once `bootstrap` has run. Available for
+    /// degenerate fits too, where `uncertainty` is not.
+    pub fn bootstrap_errors(&self) -> Option<&Bootstrap> {
+        self.bootstrap.as_ref()
+    }
+
+    /// Estimates bootstrap standard errors, refitting `resamples` times with `solver` on the
+    /// observations this solution was fitted to.
+    pub fn bootstrap(&mut self, observations: &Observations, solver: &dyn DopplerSolver, resamples: usize) {
+        self.bootstrap = uncertainty::bootstrap(self.model, observations, &self.params, solver, resamples, BOOTSTRAP_SEED);
+    }
+}
+
+// fixed, so that repeated runs print the same error bars
+const BOOTSTRAP_SEED: u64 = 0x5eed;
+
+/// How a fit ended.
+#[derive(Debug, Clone)]
+pub struct FitReport {
+    pub solver: &'static str,
+    pub termination: Termination,
+    /// Weighted sum of squared residuals at the returned parameters.
+    pub objective: f64,
+    /// Root mean square of the unweighted residuals at the returned parameters.
+    pub rms: Hz,
+    /// Iterations, or residual evaluations for Levenberg–Marquardt.
+    pub iterations: usize,
+    /// Every iterate in order, starting with the initial guess.
+    pub history: Vec<Iterate>,
+}
+
+impl FitReport {
+    pub fn success(&self) -> bool {
+        self.termination.is_success()
+    }
+}
+
+/// One step of an iterative solver.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Iterate {
+    pub iteration: usize,
+    pub params: Vec<f64>,
+    /// Weighted sum of squared residuals at `params`.
+    pub objective: f64,
+}
+
+/// Why a solver stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Termination {
+    ObjectiveBelowThreshold,
+    StepBelowTolerance,
+    /// The solver's own convergence test passed, with what it tested.
+    Converged(String),
+    IterationLimit,
+    /// The solver could not go on, e.g. no acceptable step was found.
+    Failed(String),
+}
+
+impl Termination {
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self,
+            Termination::ObjectiveBelowThreshold | Termination::StepBelowTolerance | Termination::Converged(_)
+        )
+    }
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Termination::ObjectiveBelowThreshold => write!(f, "objective below threshold"),
+            Termination::StepBelowTolerance => write!(f, "step below tolerance"),
+            Termination::Converged(how) => write!(f, "{}", how),
+            Termination::IterationLimit => write!(f, "iteration limit reached"),
+            Termination::Failed(why) => write!(f, "failed: {}", why),
+        }
+    }
+}
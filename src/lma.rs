@@ -1,132 +1,207 @@
-// use levenberg_marquardt::Problem;
-use levenberg_marquardt::LeastSquaresProblem;
+use std::cell::RefCell;
 
-use nalgebra::{DVector, Dyn, IsContiguous, Matrix3, Owned, VecStorage, Vector3, U3};
+use levenberg_marquardt::{LeastSquaresProblem, LevenbergMarquardt, TerminationReason};
+use nalgebra::{storage::Owned, DMatrix, DVector, Dyn};
 
-struct OneDeviceProblem {
-    nu0: f64,
-    x0: f64,
-    v0: f64,
+use crate::approx::{residual_rms, Progress};
+use crate::model::DopplerModel;
+use crate::solver::{Observations, StoppingCriteria};
+use crate::tracking::TrackPoint;
+use crate::{FitReport, Iterate, Termination};
+
+// inverse of the frequency uncertainty
+pub(crate) fn weight(point: &TrackPoint) -> f64 {
+    1.0 / point.uncertainty.0.max(f64::EPSILON)
+}
+
+// parameters of any `DopplerModel`, see `approx::one_device_approximation`
+struct OneDeviceProblem<'a> {
+    model: DopplerModel,
+    data: Vec<TrackPoint>,
     // not changeable
     tau0: f64,
+    p: DVector<f64>,
+    // `model.domain()`, the box the gomez solvers are held to
+    lower: Vec<f64>,
+    upper: Vec<f64>,
+    // every residual evaluation, the solver itself keeps no record
+    history: RefCell<Vec<Iterate>>,
+    progress: RefCell<Option<Progress<'a>>>,
 }
 
-/*
-impl LeastSquaresProblem<f64, U3, U3> for OneDeviceProblem {
-    type ParameterStorage = Owned<f64, U3>;
-    type ResidualStorage = Owned<f64, U3>;
-    type JacobianStorage = Owned<f64, U3, U3>;
-
-    fn set_params(&mut self, x: &nalgebra::Vector<f64, U3, Self::ParameterStorage>) {
-        
-    }
-
-    fn params(&self) -> nalgebra::Vector<f64, U3, Self::ParameterStorage> {
-        self.p
+impl OneDeviceProblem<'_> {
+    fn model(&self, t: f64) -> (f64, Vec<f64>) {
+        self.model.gradient(self.p.as_slice(), self.tau0, t)
     }
+}
 
-    fn residuals(&self) -> Option<nalgebra::Vector<f64, U3, Self::ResidualStorage>> {
-        let [x, y] = [self.p.x, self.p.y];
-        // vector containing residuals $r_1(\vec{x})$ and $r_2(\vec{x})$
-        Some(Vector3::new(x*x + y - 11., x + y*y - 7., 0.))
+impl LeastSquaresProblem<f64, Dyn, Dyn> for OneDeviceProblem<'_> {
+    type ResidualStorage = Owned<f64, Dyn>;
+    type JacobianStorage = Owned<f64, Dyn, Dyn>;
+    type ParameterStorage = Owned<f64, Dyn>;
+
+    // the domain of the gomez solvers holds here as well: a step past it leaves the model
+    // (a supersonic pass, a negative frequency), and the priors of the nuisance parameters alone
+    // can't stop a runaway into the degenerate directions
+    fn set_params(&mut self, x: &DVector<f64>) {
+        for (index, value) in x.iter().enumerate() {
+            self.p[index] = value.clamp(self.lower[index], self.upper[index]);
+        }
+        self.model.limit_speed(self.p.as_mut_slice(), &self.data);
     }
 
-    fn jacobian(&self) -> Option<nalgebra::Matrix<f64, U3, U3, Self::JacobianStorage>> {
-        let [x, y] = [self.p.x, self.p.y];
-         
-        // first row of Jacobian, derivatives of first residual
-        let d1_x = 2. * x; // $\frac{\partial}{\partial x_1}r_1(\vec{x}) = \frac{\partial}{\partial x} (x^2 + y - 11) = 2x$
-        let d1_y = 1.;     // $\frac{\partial}{\partial x_2}r_1(\vec{x}) = \frac{\partial}{\partial y} (x^2 + y - 11) = 1$
-         
-        // second row of Jacobian, derivatives of second residual
-        let d2_x = 1.;     // $\frac{\partial}{\partial x_1}r_2(\vec{x}) = \frac{\partial}{\partial x} (x + y^2 - 7) = 1$
-        let d2_y = 2. * y; // $\frac{\partial}{\partial x_2}r_2(\vec{x}) = \frac{\partial}{\partial y} (x + y^2 - 7) = 2y$
-
-        Some(Matrix3::new(
-            d1_x, d1_y, 0.,
-            d2_x, d2_y, 0.,
-            0., 0., 0.,
-        ))
+    fn params(&self) -> DVector<f64> {
+        self.p.clone()
     }
-}
-*/
 
-use nalgebra::{Vector2, U2, VectorN, Matrix2};
-use levenberg_marquardt::LevenbergMarquardt;
-
-fn _example_problem_solve() -> () {
-    struct ExampleProblem {
-        // holds current value of the n parameters
-        p: Vector2<f64>,
+    // a fitted speed of sound or wind adds the residual of its prior as a row at the end
+    fn residuals(&self) -> Option<DVector<f64>> {
+        let priors = self.model.priors(self.p.as_slice());
+        let residuals = DVector::from_iterator(
+            self.data.len() + priors.len(),
+            self.data
+                .iter()
+                .map(|y| {
+                    let (nu, _) = self.model(y.time);
+                    (nu - y.frequency.0) * weight(y)
+                })
+                .chain(priors.iter().map(|&(_, residual, _)| residual)),
+        );
+
+        let mut history = self.history.borrow_mut();
+        let iterate = Iterate {
+            iteration: history.len(),
+            params: self.p.as_slice().to_vec(),
+            objective: residuals.norm_squared(),
+        };
+        if let Some(progress) = self.progress.borrow_mut().as_deref_mut() {
+            progress(&iterate);
+        }
+        history.push(iterate);
+        Some(residuals)
     }
 
-    // We implement a trait for every problem we want to solve
-    impl LeastSquaresProblem<f64, U2, U2> for ExampleProblem {
-        type ParameterStorage = Owned<f64, U2>;
-        type ResidualStorage = Owned<f64, U2>;
-        type JacobianStorage = Owned<f64, U2, U2>;
-        
-        fn set_params(&mut self, p: &VectorN<f64, U2>) {
-            self.p.copy_from(p);
-            // do common calculations for residuals and the Jacobian here
+    fn jacobian(&self) -> Option<DMatrix<f64>> {
+        let priors = self.model.priors(self.p.as_slice());
+        let rows = self.data.len() + priors.len();
+        let mut jacobian = DMatrix::<f64>::zeros(rows, self.p.len());
+        for (x, y) in self.data.iter().enumerate() {
+            let (_, gradient) = self.model(y.time);
+            for (column, value) in gradient.iter().enumerate() {
+                jacobian[(x, column)] = value * weight(y);
+            }
         }
-        
-        fn params(&self) -> VectorN<f64, U2> { self.p }
-        
-        fn residuals(&self) -> Option<Vector2<f64>> {
-            let [x, y] = [self.p.x, self.p.y];
-            // vector containing residuals r1(x⃗)r_1(\vec{x})r1​(x) and r2(x⃗)r_2(\vec{x})r2​(x)
-            Some(Vector2::new(x*x + y - 11., x + y*y - 7.))
-        }
-        
-        fn jacobian(&self) -> Option<Matrix2<f64>> {
-            let [x, y] = [self.p.x, self.p.y];
-            
-            // first row of Jacobian, derivatives of first residual
-            let d1_x = 2. * x; // ∂∂x1r1(x⃗)=∂∂x(x2+y−11)=2x\frac{\partial}{\partial x_1}r_1(\vec{x}) = \frac{\partial}{\partial x} (x^2 + y - 11) = 2x∂x1​∂​r1​(x)=∂x∂​(x2+y−11)=2x
-            let d1_y = 1.;     // ∂∂x2r1(x⃗)=∂∂y(x2+y−11)=1\frac{\partial}{\partial x_2}r_1(\vec{x}) = \frac{\partial}{\partial y} (x^2 + y - 11) = 1∂x2​∂​r1​(x)=∂y∂​(x2+y−11)=1
-            
-            // second row of Jacobian, derivatives of second residual
-            let d2_x = 1.;     // ∂∂x1r2(x⃗)=∂∂x(x+y2−7)=1\frac{\partial}{\partial x_1}r_2(\vec{x}) = \frac{\partial}{\partial x} (x + y^2 - 7) = 1∂x1​∂​r2​(x)=∂x∂​(x+y2−7)=1
-            let d2_y = 2. * y; // ∂∂x2r2(x⃗)=∂∂y(x+y2−7)=2y\frac{\partial}{\partial x_2}r_2(\vec{x}) = \frac{\partial}{\partial y} (x + y^2 - 7) = 2y∂x2​∂​r2​(x)=∂y∂​(x+y2−7)=2y
-
-            Some(Matrix2::new(
-                d1_x, d1_y,
-                d2_x, d2_y,
-            ))
+        for (row, (column, _, derivative)) in priors.into_iter().enumerate() {
+            jacobian[(self.data.len() + row, column)] = derivative;
         }
+        Some(jacobian)
     }
+}
 
-    let problem = ExampleProblem {
-        // the initial guess for x⃗\vec{x}x
-        p: Vector2::new(1., 1.),
+/// Fits the parameters of `model` with Levenberg–Marquardt and the analytic Jacobian.
+///
+/// The reported objective is the weighted sum of squared residuals, the same quantity the
+/// gomez path minimises. `max_iterations` bounds the patience of the solver and
+/// `step_tolerance` its relative parameter and objective tolerances. The history holds every
+/// residual evaluation, rejected trial steps included.
+pub fn get_solo_approximation(
+    model: DopplerModel,
+    data: Vec<TrackPoint>,
+    tau0: f64,
+    initial: &[f64],
+    stopping: &StoppingCriteria,
+    progress: Option<Progress>,
+) -> (Vec<f64>, FitReport) {
+    let (lower, upper) = model.domain();
+    let problem = OneDeviceProblem {
+        model,
+        data,
+        tau0,
+        p: DVector::from_column_slice(initial),
+        lower,
+        upper,
+        history: RefCell::new(vec![]),
+        progress: RefCell::new(progress),
     };
-    let (_result, report) = LevenbergMarquardt::new().minimize(problem);
-    assert!(report.termination.was_successful());
-    assert!(report.objective_function.abs() < 1e-10);
+    let (result, report) = LevenbergMarquardt::new()
+        .with_patience(stopping.max_iterations.max(1))
+        .with_xtol(stopping.step_tolerance)
+        .with_ftol(stopping.step_tolerance)
+        .minimize(problem);
+
+    let params = model.canonical(result.p.as_slice());
+    let termination = termination(report.termination);
+    let observations = Observations {
+        points: result.data,
+        tau0,
+    };
+    let rms = residual_rms(model, &observations, &params);
+    (
+        params,
+        FitReport {
+            solver: "Levenberg-Marquardt",
+            termination,
+            objective: 2.0 * report.objective_function,
+            rms,
+            iterations: report.number_of_evaluations,
+            history: result.history.into_inner(),
+        },
+    )
 }
 
-fn get_solo_approximation(data: Vec<f64>) {
-    let len = data.len();
-    impl LeastSquaresProblem<f64, Dyn, Dyn> for OneDeviceProblem {
-        type ParameterStorage = DVector<f64>;
-        type ResidualStorage = Owned<f64, U3>;
-        type JacobianStorage = Owned<f64, U3, U3>;
-
-        fn set_params(&mut self, x: &nalgebra::Vector<f64, U3, Self::ParameterStorage>) {
-            
+// how the levenberg-marquardt crate's reasons map onto ours
+pub(crate) fn termination(reason: TerminationReason) -> Termination {
+    match reason {
+        TerminationReason::Converged { ftol: true, xtol: true } => {
+            Termination::Converged("objective and parameter change below tolerance".to_string())
         }
-
-        fn params(&self) -> nalgebra::Vector<f64, U3, Self::ParameterStorage> {
-            nalgebra::Vector3::new(self.nu0, self.x0, self.v0)
-        }
-
-        fn residuals(&self) -> Option<nalgebra::Vector<f64, U3, Self::ResidualStorage>> {
-            todo!()
+        TerminationReason::Converged { ftol: true, .. } => {
+            Termination::Converged("objective change below tolerance".to_string())
         }
+        TerminationReason::Converged { .. } => Termination::StepBelowTolerance,
+        TerminationReason::ResidualsZero => Termination::ObjectiveBelowThreshold,
+        TerminationReason::Orthogonal => Termination::Converged("residuals orthogonal to the Jacobian".to_string()),
+        TerminationReason::LostPatience => Termination::IterationLimit,
+        other => Termination::Failed(format!("{:?}", other)),
+    }
+}
 
-        fn jacobian(&self) -> Option<nalgebra::Matrix<f64, U3, U3, Self::JacobianStorage>> {
-            todo!()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Formula;
+    use crate::sound::{SpeedOfSound, Wind};
+    use crate::Hz;
+
+    #[test]
+    fn steps_stay_in_the_domain() {
+        let model = DopplerModel::new(Formula::Classical, SpeedOfSound::Fitted { min: 320.0, max: 360.0 }, Wind::Fixed(0.0));
+        let data = (0..10)
+            .map(|k| TrackPoint {
+                time: k as f64,
+                frequency: Hz(100.0),
+                confidence: 1.0,
+                uncertainty: Hz(1.0),
+            })
+            .collect();
+        let (lower, upper) = model.domain();
+        let mut problem = OneDeviceProblem {
+            model,
+            data,
+            tau0: 0.032,
+            p: DVector::from_column_slice(&[100.0, 50.0, 30.0, 5.0, 340.0]),
+            lower: lower.clone(),
+            upper: upper.clone(),
+            history: RefCell::new(vec![]),
+            progress: RefCell::new(None),
+        };
+        // a negative frequency, a supersonic pass and a speed of sound past its bounds
+        problem.set_params(&DVector::from_column_slice(&[-100.0, 50.0, 1000.0, 5.0, 400.0]));
+        let params = problem.params();
+        for (index, value) in params.iter().enumerate() {
+            assert!((lower[index]..=upper[index]).contains(value), "{}: {}", index, value);
         }
+        assert_eq!(params[0], 0.0);
+        assert_eq!(params[4], 360.0);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,298 @@
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Everything that can go wrong while turning a file into samples.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be opened.
+    Open(std::io::Error),
+    /// No format reader recognised the stream.
+    Probe(Error),
+    /// The container has no default (audio) track.
+    NoTrack,
+    /// No decoder is available for the track's codec.
+    Codec(Error),
+    /// Demuxing or decoding failed before any audio was produced.
+    Decode(Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Open(e) => write!(f, "cannot open file: {}", e),
+            LoadError::Probe(e) => write!(f, "unrecognised format: {}", e),
+            LoadError::NoTrack => write!(f, "no default track in the container"),
+            LoadError::Codec(e) => write!(f, "unsupported codec: {}", e),
+            LoadError::Decode(e) => write!(f, "decoding failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Open(e) => Some(e),
+            LoadError::Probe(e) | LoadError::Codec(e) | LoadError::Decode(e) => Some(e),
+            LoadError::NoTrack => None,
+        }
+    }
+}
+
+/// Decoded audio track, one sample vector per channel.
+#[derive(Debug, Clone)]
+pub struct DecodedAudio {
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// `samples[channel][frame]`, normalised to [-1, 1].
+    pub samples: Vec<Vec<f64>>,
+    /// Duration in seconds. Taken from the container header when it reports a frame count,
+    /// otherwise measured from the decoded samples.
+    pub duration: f64,
+}
+
+impl DecodedAudio {
+    /// Number of decoded frames (samples per channel).
+    pub fn frames(&self) -> usize {
+        self.samples.first().map_or(0, |channel| channel.len())
+    }
+
+    /// Mono signal: the average of all channels.
+    pub fn downmix(&self) -> Vec<f64> {
+        let scale = 1.0 / self.channels as f64;
+        (0..self.frames())
+            .map(|frame| self.samples.iter().map(|channel| channel[frame]).sum::<f64>() * scale)
+            .collect()
+    }
+
+    /// Splits the recording into the signals selected by `policy`, each tagged with its origin.
+    pub fn select_channels(
+        &self,
+        policy: ChannelPolicy,
+    ) -> Result<Vec<(AudioChannel, Vec<f64>)>, NoSuchChannel> {
+        match policy {
+            ChannelPolicy::Downmix => Ok(vec![(AudioChannel::Downmix, self.downmix())]),
+            ChannelPolicy::Pick(n) => match self.samples.get(n) {
+                Some(channel) => Ok(vec![(AudioChannel::Index(n), channel.clone())]),
+                None => Err(NoSuchChannel {
+                    requested: n,
+                    available: self.channels,
+                }),
+            },
+            ChannelPolicy::Each => Ok(self
+                .samples
+                .iter()
+                .enumerate()
+                .map(|(n, channel)| (AudioChannel::Index(n), channel.clone()))
+                .collect()),
+        }
+    }
+}
+
+/// Decodes the default track of an audio file.
+///
+/// Packets that fail to decode are skipped as long as at least one packet decodes fine.
+pub fn load_audio<P: AsRef<Path>>(path: P) -> Result<DecodedAudio, LoadError> {
+    let file = Box::new(File::open(path.as_ref()).map_err(LoadError::Open)?);
+    let mss = MediaSourceStream::new(file, Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.as_ref().extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let format_opts: FormatOptions = Default::default();
+    let metadata_opts: MetadataOptions = Default::default();
+    let decoder_opts: DecoderOptions = Default::default();
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, mss, &format_opts, &metadata_opts)
+        .map_err(LoadError::Probe)?;
+    let mut format = probed.format;
+
+    let track = format.default_track().ok_or(LoadError::NoTrack)?;
+    let track_id = track.id;
+    let header_frames = track.codec_params.n_frames;
+    let mut sample_rate = track.codec_params.sample_rate;
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &decoder_opts)
+        .map_err(LoadError::Codec)?;
+
+    let mut samples: Vec<Vec<f64>> = vec![];
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+    let mut last_error = None;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            // End of the stream
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(Error::ResetRequired) => break,
+            Err(e) => {
+                last_error = Some(e);
+                break;
+            }
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let audio_buf = match decoder.decode(&packet) {
+            Ok(audio_buf) => audio_buf,
+            // Corrupted packet, the next one may still be fine
+            Err(Error::DecodeError(e)) => {
+                last_error = Some(Error::DecodeError(e));
+                continue;
+            }
+            Err(e) => {
+                last_error = Some(e);
+                break;
+            }
+        };
+
+        let spec = *audio_buf.spec();
+        let channels = spec.channels.count();
+        let frames = audio_buf.frames();
+        sample_rate.get_or_insert(spec.rate);
+        if samples.is_empty() {
+            samples = vec![vec![]; channels];
+        }
+        if frames == 0 || channels != samples.len() {
+            continue;
+        }
+
+        // The buffer is sized by the first packet, grow it if a later packet is larger.
+        let required = audio_buf.capacity() * channels;
+        if sample_buf.as_ref().is_none_or(|buf| buf.capacity() < required) {
+            sample_buf = Some(SampleBuffer::<f32>::new(audio_buf.capacity() as u64, spec));
+        }
+        let buf = sample_buf.as_mut().unwrap();
+        buf.copy_planar_ref(audio_buf);
+
+        for (channel, planar) in samples.iter_mut().zip(buf.samples().chunks(frames)) {
+            channel.extend(planar.iter().map(|f| *f as f64));
+        }
+    }
+
+    let decoded_frames = samples.first().map_or(0, |channel| channel.len());
+    if decoded_frames == 0 {
+        return Err(LoadError::Decode(
+            last_error.unwrap_or(Error::DecodeError("no audio frames decoded")),
+        ));
+    }
+    let sample_rate = sample_rate.unwrap();
+
+    Ok(DecodedAudio {
+        sample_rate,
+        channels: samples.len(),
+        samples,
+        duration: duration(header_frames, decoded_frames, sample_rate),
+    })
+}
+
+// seconds of the frame count in the header, or of the decoded frames if it has none
+fn duration(header_frames: Option<u64>, decoded_frames: usize, sample_rate: u32) -> f64 {
+    header_frames.unwrap_or(decoded_frames as u64) as f64 / sample_rate as f64
+}
+
+/// Which part of a multi-channel recording a signal was taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioChannel {
+    /// Average of all channels.
+    Downmix,
+    /// A single channel, counted from zero.
+    Index(usize),
+}
+
+impl fmt::Display for AudioChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioChannel::Downmix => write!(f, "mono"),
+            AudioChannel::Index(n) => write!(f, "ch{}", n),
+        }
+    }
+}
+
+/// How the channels of a recording are turned into the signals that get analysed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelPolicy {
+    /// Mix every channel down to one mono signal.
+    #[default]
+    Downmix,
+    /// Analyse only the given channel.
+    Pick(usize),
+    /// Analyse every channel separately.
+    Each,
+}
+
+/// Returned when `ChannelPolicy::Pick` names a channel the recording doesn't have.
+#[derive(Debug, Clone, Copy)]
+pub struct NoSuchChannel {
+    pub requested: usize,
+    pub available: usize,
+}
+
+impl fmt::Display for NoSuchChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "channel {} requested, but the recording has {} channel(s)",
+            self.requested, self.available
+        )
+    }
+}
+
+impl std::error::Error for NoSuchChannel {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_file_fails_to_open() {
+        match load_audio("test-audio/no such file.mp3") {
+            Err(LoadError::Open(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("{:?}", other.map(|audio| audio.duration)),
+        }
+    }
+
+    #[test]
+    fn duration_falls_back_to_the_decoded_frames() {
+        assert_eq!(duration(Some(96_000), 95_000, 48_000), 2.0);
+        assert_eq!(duration(None, 24_000, 48_000), 0.5);
+    }
+
+    fn stereo() -> DecodedAudio {
+        DecodedAudio {
+            sample_rate: 8000,
+            channels: 2,
+            samples: vec![vec![1.0, 0.5, -1.0], vec![0.0, 0.5, 0.0]],
+            duration: 3.0 / 8000.0,
+        }
+    }
+
+    #[test]
+    fn downmix_averages_the_channels() {
+        assert_eq!(stereo().downmix(), vec![0.5, 0.5, -0.5]);
+        let signals = stereo().select_channels(ChannelPolicy::Downmix).unwrap();
+        assert_eq!(signals, vec![(AudioChannel::Downmix, vec![0.5, 0.5, -0.5])]);
+    }
+
+    #[test]
+    fn picking_a_missing_channel_fails() {
+        let error = stereo().select_channels(ChannelPolicy::Pick(2)).unwrap_err();
+        assert_eq!((error.requested, error.available), (2, 2));
+        let signals = stereo().select_channels(ChannelPolicy::Pick(1)).unwrap();
+        assert_eq!(signals, vec![(AudioChannel::Index(1), vec![0.0, 0.5, 0.0])]);
+    }
+}
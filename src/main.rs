@@ -1,289 +1,1256 @@
 use std::env;
-use std::fs::File;
-use std::path::Path;
-
-use symphonia::core::audio::SampleBuffer;
-use symphonia::core::codecs::DecoderOptions;
-use symphonia::core::errors::Error;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
-
-pub mod plot;
-#[allow(unused_imports)]
-use crate::approx::{example_usage, one_device_approximation};
-#[allow(unused_imports)]
-use crate::plot::{gif_plots, plot};
-// pub mod lma;
-pub mod approx;
-
-fn get_duration(track: &symphonia::core::formats::Track) -> f64 {
-    if track.codec_params.n_frames.is_none() {
-        panic!("No n_frames in the track.codec_params");
-    }
-    let n_frames = track.codec_params.n_frames.unwrap();
-    println!("Number of frames: {}", n_frames);
-    if track.codec_params.sample_rate.is_none() {
-        panic!("No sample_rate in the track.codec_params");   
-    }
-    let sample_rate = track.codec_params.sample_rate.unwrap();
-    let seconds = n_frames as f64 / sample_rate as f64;
-    println!("Duration (seconds): {:.2}", seconds);
-    seconds
+use std::process::ExitCode;
+use std::time::Instant;
+
+use radaurio::approx::{one_device_approximation, unexplained};
+use radaurio::align::{
+    common_span, estimate_offset, on_reference_clock, AlignmentConfig, AlignmentMethod, ClockOffset, OffsetTable,
+};
+use radaurio::denoise::{denoise, NoiseEstimator, NoiseReduction};
+use radaurio::filter::{below_nyquist, FilterChain};
+use radaurio::geometry::{Orientation, Side};
+use radaurio::guess::estimate_pass;
+use radaurio::loader::{load_audio, AudioChannel, ChannelPolicy, DecodedAudio};
+use radaurio::model::{DopplerModel, Formula};
+use radaurio::multi::{multi_device_approximation, Altitude, ArrayModel, MultiDeviceSolution, Recording};
+use radaurio::plot::{plot_to, OUT_FILE_NAME};
+use radaurio::selection::{select, Criterion};
+use radaurio::smoothing::{smooth, Smoothing, SmoothingConfig};
+use radaurio::solver::{Observations, SolverBackend, StoppingCriteria};
+use radaurio::sound::{Air, SpeedOfSound, Wind};
+use radaurio::spectrum::{stft, Hz, StftConfig, Window};
+use radaurio::tdoa::{bearing, frame_delays, multilaterate, Delay, TdoaConfig};
+use radaurio::tracking::{
+    kalman_smooth, track_fundamental, track_peaks, track_viterbi, HarmonicConfig, HarmonicMethod,
+    KalmanConfig, PeakInterpolation, TrackPoint, ViterbiConfig,
+};
+use radaurio::trajectory::{KnownAltitude, KnownOffset, Trajectory};
+use radaurio::{Iterate, OneDeviceSolution};
+
+struct Options {
+    // more than one: recordings of the same pass, fitted together
+    files: Vec<String>,
+    // east/north/up of the microphone of each file
+    microphones: Vec<[f64; 3]>,
+    // with several files: also fit the delays between them
+    tdoa: Option<TdoaConfig>,
+    // with several files: only estimate their clocks and write the table to this file
+    align: Option<(String, AlignmentConfig)>,
+    // with several files: table of their clocks to put them on the clock of the first
+    offsets: Option<String>,
+    channels: ChannelPolicy,
+    stft: StftConfig,
+    smoothing: Option<SmoothingConfig>,
+    interpolation: PeakInterpolation,
+    tracker: Tracker,
+    kalman: Option<KalmanConfig>,
+    // fit the track of every harmonic, not their combined fundamental
+    fit_harmonics: bool,
+    band: Option<(Hz, Hz)>,
+    highpass: Option<Hz>,
+    lowpass: Option<Hz>,
+    notch: Option<Hz>,
+    denoise: Option<(NoiseEstimator, NoiseReduction)>,
+    // more than one: fit with each and compare
+    solvers: Vec<SolverBackend>,
+    model: DopplerModel,
+    // more than one: fit each and keep the one the criterion prefers
+    trajectories: Vec<Trajectory>,
+    criterion: Criterion,
+    // places the fitted pass on the map
+    orientation: Option<Orientation>,
+    stopping: StoppingCriteria,
+    // print every solver iterate
+    trace: bool,
+    // bootstrap resamples for the error bars
+    bootstrap: Option<usize>,
+}
+
+enum Tracker {
+    Peak,
+    Harmonic(HarmonicConfig),
+    Viterbi(ViterbiConfig),
+}
+
+const OPTIONS_HELP: &str = "\
+options:
+  --channel mono|each|<N>       signal to analyse (default: mono downmix)
+  --frame-len <samples>         STFT frame length (default: 4096)
+  --hop <samples>               STFT hop size (default: 1024)
+  --window <name>               rect, hann, hamming, blackman-harris, flat-top or kaiser:<beta>
+                                (default: hann)
+  --fft-len <samples>           zero-pad frames up to this FFT size
+  --smooth <name>               none, mean, exponential, welch[:<overlap>] or median
+                                temporal smoothing of the spectrogram (default: welch:0.5)
+  --smooth-window <seconds>     smoothing window or time constant (default: 0.25)
+  --interpolation <name>        none, parabolic, gaussian, quinn or phase-vocoder
+                                (default: parabolic)
+  --tracker <name>              peak, harmonic-sum, harmonic-product or viterbi (default: viterbi)
+  --harmonics <n>               harmonics used by the harmonic trackers (default: 5)
+  --fit-harmonics               with a harmonic tracker, fit the track of every harmonic divided
+                                by its order instead of their combined fundamental
+  --fundamental <lo>:<hi>       fundamental search range in Hz (default: 40:500)
+  --band <lo>:<hi>              search band in Hz (default: everything for peak, 40:2000 for viterbi)
+  --slew <Hz/s>                 typical frequency change rate for viterbi (default: 50)
+  --kalman                      smooth the track with a Kalman filter before fitting
+  --highpass <Hz>               4th-order Butterworth high-pass before the STFT
+  --lowpass <Hz>                4th-order Butterworth low-pass before the STFT
+  --bandpass <lo>:<hi>          both of the above
+  --notch <Hz>                  notch out mains hum at this frequency and its harmonics
+  --denoise <name>              subtract, wiener or whiten the spectrogram against its noise floor
+  --noise-floor <name>          median or min-stats:<seconds> (default: median)
+  --solver <name>               trust-region, nelder-mead, lm, grid, or all to benchmark them
+                                against each other (default: trust-region)
+  --model <name>                classical, fitting the source frequency, or legacy
+                                (default: classical)
+  --trajectory <name>           straight, acceleration, turn, altitude (ground offset and
+                                altitude apart, which one microphone can only split by the
+                                initial guess), or select to pick among the first three by
+                                --criterion (default: straight); altitude:<m> or offset:<m> fixes the
+                                altitude above, or the ground offset from, the microphone (the
+                                first file's with several files)
+  --criterion <name>            aic or bic, for --trajectory select (default: bic)
+  --microphone <e>:<n>[:<u>]    position of the microphone in m east, north and up, once per
+                                file in the order of the files; with several files, which must
+                                have been started together unless --offsets says otherwise, one
+                                straight level track is fitted to all of them, with its altitude
+                                unless altitude:<m> gives it
+  --tdoa                        with several files, also measure the delays between them with
+                                GCC-PHAT and fit the track to those as well
+  --align <table file>          with several files, only estimate the clock offset of each
+                                against the first and write them to the table file
+  --align-method <name>         clap, on the strongest transient (phones held together),
+                                envelope, or drift to also fit a clock drift (default: envelope)
+  --max-offset <seconds>        largest clock offset searched by --align (default: 30)
+  --offsets <table file>        clock offsets from --align of the files, which then need not
+                                have been started together
+  --heading <degrees>[:<side>]  direction of flight from north, and left or right for the side
+                                of the track the microphone is on (default: right), to report
+                                positions east/north/up of the microphone
+  --temperature <Celsius>       air temperature, sets the speed of sound (default: 20)
+  --humidity <percent>          relative humidity for the speed of sound (default: 0)
+  --site-elevation <m>          height of the site above sea level for the speed of sound
+                                (default: 0)
+  --speed-of-sound <value>      m/s, or fit[:<min>:<max>] to fit it as a nuisance parameter
+                                (default: 343, or from the options above)
+  --wind <value>                m/s along the track, positive in the direction of flight, or
+                                fit[:<min>:<max>] to fit it (default: 0)
+  --retarded-time               place the source where it emitted what is heard, not where it
+                                is when it is heard
+  --max-iterations <n>          iteration limit of the solver (default: 100)
+  --tolerance <value>           stop once a step moves the parameters less than this
+                                (default: 1e-8)
+  --trace                       print every solver iteration
+  --bootstrap <n>               also estimate error bars by refitting n resamples of the frames";
+
+fn usage(program: &str) -> String {
+    format!("usage: {} <audio file>... [options]\n{}", program, OPTIONS_HELP)
 }
 
-fn decode_image(file_path: &String) -> (Vec<Vec<f64>>, f64) {
-    // Return vector of vector samples
-    let mut samples: Vec<Vec<f64>> = vec![];
-
-    // Create a media source. Note that the MediaSource trait is automatically implemented for File,
-    // among other types.
-    let file = Box::new(File::open(Path::new(file_path)).unwrap());
-
-    // Create the media source stream using the boxed media source from above.
-    let mss = MediaSourceStream::new(file, Default::default());
-
-    // Create a hint to help the format registry guess what format reader is appropriate. In this
-    // example we'll leave it empty.
-    let hint = Hint::new();
-
-    // Use the default options when reading and decoding.
-    let format_opts: FormatOptions = Default::default();
-    let metadata_opts: MetadataOptions = Default::default();
-    let decoder_opts: DecoderOptions = Default::default();
-
-    // Probe the media source stream for a format.
-    let probed = symphonia::default::get_probe()
-        .format(&hint, mss, &format_opts, &metadata_opts)
-        .unwrap();
-
-    // Get the format reader yielded by the probe operation.
-    let mut format = probed.format;
-
-    // Get the default track.
-    let track = format.default_track().unwrap();
-    
-    let duration = get_duration(track);
-    println!("duration of the file: {}", duration);
-
-    // Create a decoder for the track.
-    let mut decoder = symphonia::default::get_codecs()
-        .make(&track.codec_params, &decoder_opts)
-        .unwrap();
-
-    // Store the track identifier, we'll use it to filter packets.
-    let track_id = track.id;
-
-    let mut min_len = usize::MAX;
-    let mut max_len = usize::MIN;
-    let mut sample_count = 0;
-    let mut sample_buf = None;
-
-    loop {
-        // Get the next packet from the format reader.
-        let result = format.next_packet();
-        // End of a file break
-        if let Err(e) = result {
-            match e {
-                Error::IoError(io_err) if io_err.kind() == std::io::ErrorKind::UnexpectedEof => {
-                    println!("=== end of file error");
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let program = args.first().map_or("radaurio", |s| s.as_str());
+    let mut files = vec![];
+    let mut microphones = vec![];
+    let mut tdoa = None;
+    let mut align = None;
+    let mut alignment = AlignmentConfig::default();
+    let mut offsets = None;
+    let mut channels = ChannelPolicy::default();
+    let mut stft = StftConfig::default();
+    let mut smoothing = Some(SmoothingConfig::default());
+    let mut smooth_window = None;
+    let mut interpolation = PeakInterpolation::default();
+    let mut tracker = "viterbi";
+    let mut harmonic = HarmonicConfig::default();
+    let mut viterbi = ViterbiConfig::default();
+    let mut kalman = None;
+    let mut fit_harmonics = false;
+    let mut band = None;
+    let (mut highpass, mut lowpass, mut notch) = (None, None, None);
+    let mut reduction = None;
+    let mut estimator = NoiseEstimator::Median;
+    let mut solvers = vec![SolverBackend::default()];
+    let mut formula = Formula::default();
+    let mut air = None;
+    let mut speed_of_sound = None;
+    let mut wind = None;
+    let mut retarded_time = false;
+    let mut trajectories = vec![Trajectory::Straight];
+    let mut criterion = Criterion::default();
+    let mut orientation = None;
+    let mut stopping = StoppingCriteria::default();
+    let mut trace = false;
+    let mut bootstrap = None;
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = |name: &str| {
+            iter.next()
+                .ok_or_else(|| format!("{} expects a value\n{}", name, usage(program)))
+        };
+        match arg.as_str() {
+            "--channel" => {
+                channels = match value("--channel")?.as_str() {
+                    "mono" => ChannelPolicy::Downmix,
+                    "each" => ChannelPolicy::Each,
+                    n => ChannelPolicy::Pick(
+                        n.parse()
+                            .map_err(|_| format!("invalid --channel value: {}", n))?,
+                    ),
+                }
+            }
+            "--frame-len" => stft.frame_len = parse_count("--frame-len", value("--frame-len")?)?,
+            "--hop" => stft.hop = parse_count("--hop", value("--hop")?)?,
+            "--window" => stft.window = parse_window(value("--window")?)?,
+            "--fft-len" => stft.fft_len = Some(parse_count("--fft-len", value("--fft-len")?)?),
+            "--smooth" => {
+                let name = value("--smooth")?;
+                let mode = match name.as_str() {
+                    "none" => None,
+                    "mean" => Some(Smoothing::SlidingMean),
+                    "exponential" => Some(Smoothing::Exponential),
+                    "welch" => Some(Smoothing::Welch { overlap: 0.5 }),
+                    "median" => Some(Smoothing::Median),
+                    _ => match name.strip_prefix("welch:").map(str::parse::<f64>) {
+                        Some(Ok(overlap)) if (0.0..1.0).contains(&overlap) => {
+                            Some(Smoothing::Welch { overlap })
+                        }
+                        _ => return Err(format!("invalid --smooth value: {}", name)),
+                    },
+                };
+                smoothing = mode.map(|mode| SmoothingConfig {
+                    mode,
+                    ..SmoothingConfig::default()
+                });
+            }
+            "--smooth-window" => {
+                let window = value("--smooth-window")?;
+                smooth_window = match window.parse::<f64>() {
+                    Ok(seconds) if seconds > 0.0 => Some(seconds),
+                    _ => return Err(format!("invalid --smooth-window value: {}", window)),
                 }
-                _ => {
-                    println!("=== unknown error: {}", e);
+            }
+            "--interpolation" => {
+                interpolation = match value("--interpolation")?.as_str() {
+                    "none" => PeakInterpolation::None,
+                    "parabolic" => PeakInterpolation::Parabolic,
+                    "gaussian" => PeakInterpolation::Gaussian,
+                    "quinn" => PeakInterpolation::Quinn,
+                    "phase-vocoder" => PeakInterpolation::PhaseVocoder,
+                    other => return Err(format!("invalid --interpolation value: {}", other)),
                 }
             }
-            // std::process::exit(0);
-            break;
+            "--tracker" => {
+                tracker = match value("--tracker")?.as_str() {
+                    "peak" => "peak",
+                    "harmonic-sum" => {
+                        harmonic.method = HarmonicMethod::Sum;
+                        "harmonic"
+                    }
+                    "harmonic-product" => {
+                        harmonic.method = HarmonicMethod::Product;
+                        "harmonic"
+                    }
+                    "viterbi" => "viterbi",
+                    other => return Err(format!("invalid --tracker value: {}", other)),
+                }
+            }
+            "--harmonics" => harmonic.harmonics = parse_count("--harmonics", value("--harmonics")?)?,
+            "--fundamental" => {
+                let (lo, hi) = parse_band("--fundamental", value("--fundamental")?)?;
+                harmonic.min_fundamental = lo;
+                harmonic.max_fundamental = hi;
+            }
+            "--band" => {
+                let (lo, hi) = parse_band("--band", value("--band")?)?;
+                viterbi.min_frequency = lo;
+                viterbi.max_frequency = hi;
+                band = Some((lo, hi));
+            }
+            "--slew" => {
+                let slew = value("--slew")?;
+                viterbi.slew = match slew.parse() {
+                    Ok(slew) if slew > 0.0 => slew,
+                    _ => return Err(format!("invalid --slew value: {}", slew)),
+                }
+            }
+            "--kalman" => kalman = Some(KalmanConfig::default()),
+            "--fit-harmonics" => fit_harmonics = true,
+            "--highpass" => highpass = Some(parse_frequency("--highpass", value("--highpass")?)?),
+            "--lowpass" => lowpass = Some(parse_frequency("--lowpass", value("--lowpass")?)?),
+            "--bandpass" => {
+                let (lo, hi) = parse_band("--bandpass", value("--bandpass")?)?;
+                highpass = Some(lo);
+                lowpass = Some(hi);
+            }
+            "--notch" => notch = Some(parse_frequency("--notch", value("--notch")?)?),
+            "--denoise" => {
+                reduction = Some(match value("--denoise")?.as_str() {
+                    "subtract" => NoiseReduction::Subtraction {
+                        over_subtraction: 2.0,
+                        floor: 0.01,
+                    },
+                    "wiener" => NoiseReduction::Wiener { min_gain: 0.1 },
+                    "whiten" => NoiseReduction::Whitening,
+                    other => return Err(format!("invalid --denoise value: {}", other)),
+                })
+            }
+            "--noise-floor" => {
+                let name = value("--noise-floor")?;
+                estimator = match name.as_str() {
+                    "median" => NoiseEstimator::Median,
+                    _ => match name.strip_prefix("min-stats:").map(str::parse::<f64>) {
+                        Some(Ok(window)) if window > 0.0 => {
+                            NoiseEstimator::MinimumStatistics { window }
+                        }
+                        _ => return Err(format!("invalid --noise-floor value: {}", name)),
+                    },
+                }
+            }
+            "--solver" => {
+                solvers = match value("--solver")?.as_str() {
+                    "trust-region" => vec![SolverBackend::TrustRegion],
+                    "nelder-mead" => vec![SolverBackend::NelderMead],
+                    "lm" => vec![SolverBackend::LevenbergMarquardt],
+                    "grid" => vec![SolverBackend::GridSearch],
+                    "all" => SolverBackend::ALL.to_vec(),
+                    other => return Err(format!("invalid --solver value: {}", other)),
+                }
+            }
+            "--model" => {
+                formula = match value("--model")?.as_str() {
+                    "classical" => Formula::Classical,
+                    "legacy" => Formula::Legacy,
+                    other => return Err(format!("invalid --model value: {}", other)),
+                }
+            }
+            "--temperature" => {
+                let temperature = value("--temperature")?;
+                air.get_or_insert_with(Air::default).temperature = match temperature.parse::<f64>() {
+                    Ok(temperature) if temperature > -273.15 => temperature,
+                    _ => return Err(format!("invalid --temperature value: {}", temperature)),
+                }
+            }
+            "--humidity" => {
+                let humidity = value("--humidity")?;
+                air.get_or_insert_with(Air::default).relative_humidity = match humidity.parse::<f64>() {
+                    Ok(humidity) if (0.0..=100.0).contains(&humidity) => humidity,
+                    _ => return Err(format!("invalid --humidity value: {}", humidity)),
+                }
+            }
+            "--site-elevation" => {
+                let elevation = value("--site-elevation")?;
+                air.get_or_insert_with(Air::default).elevation = elevation
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid --site-elevation value: {}", elevation))?
+            }
+            "--speed-of-sound" => speed_of_sound = Some(parse_speed_of_sound(value("--speed-of-sound")?)?),
+            "--wind" => wind = Some(parse_wind(value("--wind")?)?),
+            "--retarded-time" => retarded_time = true,
+            "--trajectory" => {
+                trajectories = match value("--trajectory")?.as_str() {
+                    "straight" => vec![Trajectory::Straight],
+                    "acceleration" => vec![Trajectory::ConstantAcceleration],
+                    "turn" => vec![Trajectory::Turn],
+                    "altitude" => vec![Trajectory::AltitudeOffset],
+                    "select" => Trajectory::ALL.to_vec(),
+                    other => vec![parse_known_trajectory(other)?],
+                }
+            }
+            "--heading" => orientation = Some(parse_heading(value("--heading")?)?),
+            "--microphone" => microphones.push(parse_microphone(value("--microphone")?)?),
+            "--tdoa" => tdoa = Some(TdoaConfig::default()),
+            "--align" => align = Some(value("--align")?.clone()),
+            "--align-method" => {
+                alignment.method = match value("--align-method")?.as_str() {
+                    "clap" => AlignmentMethod::Clap,
+                    "envelope" => AlignmentMethod::Envelope,
+                    "drift" => AlignmentMethod::EnvelopeDrift,
+                    other => return Err(format!("invalid --align-method value: {}", other)),
+                }
+            }
+            "--max-offset" => {
+                let max_offset = value("--max-offset")?;
+                alignment.max_offset = match max_offset.parse::<f64>() {
+                    Ok(max_offset) if max_offset > 0.0 => max_offset,
+                    _ => return Err(format!("invalid --max-offset value: {}", max_offset)),
+                }
+            }
+            "--offsets" => offsets = Some(value("--offsets")?.clone()),
+            "--criterion" => {
+                criterion = match value("--criterion")?.as_str() {
+                    "aic" => Criterion::Aic,
+                    "bic" => Criterion::Bic,
+                    other => return Err(format!("invalid --criterion value: {}", other)),
+                }
+            }
+            "--max-iterations" => {
+                stopping.max_iterations = parse_count("--max-iterations", value("--max-iterations")?)?
+            }
+            "--tolerance" => {
+                let tolerance = value("--tolerance")?;
+                stopping.step_tolerance = match tolerance.parse::<f64>() {
+                    Ok(tolerance) if tolerance >= 0.0 => tolerance,
+                    _ => return Err(format!("invalid --tolerance value: {}", tolerance)),
+                }
+            }
+            "--trace" => trace = true,
+            "--bootstrap" => bootstrap = Some(parse_count("--bootstrap", value("--bootstrap")?)?),
+            _ if !arg.starts_with("--") => files.push(arg.clone()),
+            _ => return Err(format!("unexpected argument: {}\n{}", arg, usage(program))),
         }
-        let packet = result.unwrap();
+    }
 
-        // If the packet does not belong to the selected track, skip it.
-        if packet.track_id() != track_id {
-            // println!("skip packet");
-            continue;
+    if stft.fft_len.is_some_and(|fft_len| fft_len < stft.frame_len) {
+        return Err("--fft-len must not be smaller than --frame-len".to_string());
+    }
+    if wind.is_some() && formula == Formula::Legacy {
+        return Err("--wind needs the classical model".to_string());
+    }
+    if retarded_time && formula == Formula::Legacy {
+        return Err("--retarded-time needs the classical model".to_string());
+    }
+    if trajectories != [Trajectory::Straight] && formula == Formula::Legacy {
+        return Err("--trajectory needs the classical model".to_string());
+    }
+    if fit_harmonics && tracker != "harmonic" {
+        return Err("--fit-harmonics needs --tracker harmonic-sum or harmonic-product".to_string());
+    }
+    if files.is_empty() {
+        return Err(usage(program));
+    }
+    if align.is_some() {
+        if files.len() < 2 {
+            return Err("--align needs several files".to_string());
         }
+        if offsets.is_some() {
+            return Err("--align makes the table that --offsets reads; give one of them".to_string());
+        }
+    } else if files.len() > 1 {
+        if microphones.len() != files.len() {
+            return Err("several files need one --microphone each".to_string());
+        }
+        if formula == Formula::Legacy {
+            return Err("several files need the classical model".to_string());
+        }
+        if !matches!(trajectories[..], [Trajectory::Straight] | [Trajectory::KnownAltitude(_)]) {
+            return Err("several files take --trajectory straight or altitude:<m>".to_string());
+        }
+        if channels == ChannelPolicy::Each {
+            return Err("several files take one channel each".to_string());
+        }
+    } else if !microphones.is_empty() {
+        return Err("--microphone needs several files".to_string());
+    } else if tdoa.is_some() {
+        return Err("--tdoa needs several files".to_string());
+    } else if offsets.is_some() {
+        return Err("--offsets needs several files".to_string());
+    }
 
-        // Decode the packet into audio samples, ignoring any decode errors.
-        match decoder.decode(&packet) {
-            Ok(audio_buf) => {
-                // The decoded audio samples may now be accessed via the audio buffer if per-channel
-                // slices of samples in their native decoded format is desired. Use-cases where
-                // the samples need to be accessed in an interleaved order or converted into
-                // another sample format, or a byte buffer is required, are covered by copying the
-                // audio buffer into a sample buffer or raw sample buffer, respectively. In the
-                // example below, we will copy the audio buffer into a sample buffer in an
-                // interleaved order while also converting to a f32 sample format.
-
-                // If this is the *first* decoded packet, create a sample buffer matching the
-                // decoded audio buffer format.
-                if sample_buf.is_none() {
-                    // Get the audio buffer specification.
-                    let spec = *audio_buf.spec();
-
-                    // Get the capacity of the decoded buffer. Note: This is capacity, not length!
-                    let duration = audio_buf.capacity() as u64;
-
-                    // Create the f32 sample buffer.
-                    sample_buf = Some(SampleBuffer::<f32>::new(duration, spec));
-                }
+    Ok(Options {
+        files,
+        microphones,
+        tdoa,
+        align: align.map(|table| (table, alignment)),
+        offsets,
+        channels,
+        stft,
+        smoothing: smoothing.map(|config| SmoothingConfig {
+            window: smooth_window.unwrap_or(config.window),
+            ..config
+        }),
+        interpolation,
+        tracker: match tracker {
+            "harmonic" => Tracker::Harmonic(harmonic),
+            "peak" => Tracker::Peak,
+            _ => Tracker::Viterbi(viterbi),
+        },
+        kalman,
+        fit_harmonics,
+        band,
+        highpass,
+        lowpass,
+        notch,
+        denoise: reduction.map(|reduction| (estimator, reduction)),
+        solvers,
+        model: DopplerModel {
+            retarded_time,
+            ..DopplerModel::new(
+                formula,
+                speed_of_sound.unwrap_or_else(|| match air {
+                    Some(air) => SpeedOfSound::Fixed(air.speed_of_sound()),
+                    None => SpeedOfSound::default(),
+                }),
+                wind.unwrap_or_default(),
+            )
+        },
+        trajectories,
+        criterion,
+        orientation,
+        stopping,
+        trace,
+        bootstrap,
+    })
+}
 
-                // Copy the decoded audio buffer into the sample buffer in an interleaved format.
-                if let Some(buf) = &mut sample_buf {
-                    buf.copy_interleaved_ref(audio_buf);
-
-                    // The samples may now be access via the `samples()` function.
-                    sample_count += buf.samples().len();
-                    min_len = min_len.min(buf.samples().len());
-                    max_len = max_len.max(buf.samples().len());
-                    print!(
-                        "\rDecoded {} samples, value: {}",
-                        sample_count,
-                        buf.samples().last().unwrap()
-                    );
-                    // plot(buf.samples().into_iter().map(|f| *f as f64).collect()).unwrap();
-                    // break;
-                    //total.push(*buf.samples().last().unwrap() as f64);
-                    // for value in buf.samples() {
-                    //     total.push(*value as f64);
-                    // }
-                    samples.push(buf.samples().into_iter().map(|f| *f as f64).collect());
-                }
-            }
-            Err(Error::DecodeError(_)) => (),
-            Err(_) => break,
+fn parse_speed_of_sound(value: &str) -> Result<SpeedOfSound, String> {
+    if value == "fit" {
+        return Ok(SpeedOfSound::FITTED);
+    }
+    let invalid = || format!("invalid --speed-of-sound value: {}", value);
+    if let Some(range) = value.strip_prefix("fit:") {
+        let (min, max) = range.split_once(':').ok_or_else(invalid)?;
+        return match (min.parse::<f64>(), max.parse::<f64>()) {
+            (Ok(min), Ok(max)) if 0.0 < min && min < max => Ok(SpeedOfSound::Fitted { min, max }),
+            _ => Err(invalid()),
+        };
+    }
+    match value.parse::<f64>() {
+        Ok(c) if c > 0.0 => Ok(SpeedOfSound::Fixed(c)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_wind(value: &str) -> Result<Wind, String> {
+    if value == "fit" {
+        return Ok(Wind::FITTED);
+    }
+    let invalid = || format!("invalid --wind value: {}", value);
+    if let Some(range) = value.strip_prefix("fit:") {
+        let (min, max) = range.split_once(':').ok_or_else(invalid)?;
+        return match (min.parse::<f64>(), max.parse::<f64>()) {
+            (Ok(min), Ok(max)) if min < max => Ok(Wind::Fitted { min, max }),
+            _ => Err(invalid()),
+        };
+    }
+    value.parse::<f64>().map(Wind::Fixed).map_err(|_| invalid())
+}
+
+// altitude:<m> or offset:<m>
+fn parse_known_trajectory(value: &str) -> Result<Trajectory, String> {
+    let invalid = || format!("invalid --trajectory value: {}", value);
+    let (name, known) = value.split_once(':').ok_or_else(invalid)?;
+    let known = known.parse::<f64>().map_err(|_| invalid())?;
+    match name {
+        "altitude" => Ok(Trajectory::KnownAltitude(KnownAltitude { altitude: known })),
+        "offset" => Ok(Trajectory::KnownOffset(KnownOffset { offset: known.abs() })),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_heading(value: &str) -> Result<Orientation, String> {
+    let invalid = || format!("invalid --heading value: {}", value);
+    let (heading, side) = match value.split_once(':') {
+        Some((heading, "left")) => (heading, Side::Left),
+        Some((heading, "right")) => (heading, Side::Right),
+        Some(_) => return Err(invalid()),
+        None => (value, Side::default()),
+    };
+    match heading.parse::<f64>() {
+        Ok(heading) if heading.is_finite() => Ok(Orientation {
+            heading: heading.rem_euclid(360.0),
+            side,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_microphone(value: &str) -> Result<[f64; 3], String> {
+    let invalid = || format!("invalid --microphone value: {}", value);
+    let coordinates = value
+        .split(':')
+        .map(|coordinate| coordinate.parse::<f64>().ok().filter(|c| c.is_finite()))
+        .collect::<Option<Vec<f64>>>()
+        .ok_or_else(invalid)?;
+    match coordinates[..] {
+        [east, north] => Ok([east, north, 0.0]),
+        [east, north, up] => Ok([east, north, up]),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_window(value: &str) -> Result<Window, String> {
+    Ok(match value {
+        "rect" => Window::Rectangular,
+        "hann" => Window::Hann,
+        "hamming" => Window::Hamming,
+        "blackman-harris" => Window::BlackmanHarris,
+        "flat-top" => Window::FlatTop,
+        _ => match value.strip_prefix("kaiser:").map(str::parse::<f64>) {
+            Some(Ok(beta)) if beta >= 0.0 => Window::Kaiser(beta),
+            _ => return Err(format!("invalid --window value: {}", value)),
+        },
+    })
+}
+
+fn parse_band(name: &str, value: &str) -> Result<(Hz, Hz), String> {
+    let invalid = || format!("{} expects <lo>:<hi> in Hz, got {}", name, value);
+    let (lo, hi) = value.split_once(':').ok_or_else(invalid)?;
+    match (lo.parse::<f64>(), hi.parse::<f64>()) {
+        (Ok(lo), Ok(hi)) if 0.0 <= lo && lo < hi => Ok((Hz(lo), Hz(hi))),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_frequency(name: &str, value: &str) -> Result<Hz, String> {
+    match value.parse() {
+        Ok(frequency) if frequency > 0.0 => Ok(Hz(frequency)),
+        _ => Err(format!("{} expects a positive frequency in Hz, got {}", name, value)),
+    }
+}
+
+fn parse_count(name: &str, value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("{} expects a positive integer, got {}", name, value)),
+    }
+}
+
+// filters, spectrogram and tracker of the options; returns the track and its frame duration
+fn extract_track(audio: &DecodedAudio, signal: &[f64], options: &Options) -> Result<(Vec<TrackPoint>, f64), String> {
+    for (name, cutoff) in [("--highpass", options.highpass), ("--lowpass", options.lowpass)] {
+        if let Some(cutoff) = cutoff.filter(|cutoff| !below_nyquist(audio.sample_rate, *cutoff)) {
+            return Err(format!(
+                "{} {} is not below the Nyquist frequency of {} Hz",
+                name,
+                cutoff,
+                audio.sample_rate / 2
+            ));
         }
     }
+    let mut filters = FilterChain::new();
+    if let Some(cutoff) = options.highpass {
+        filters = filters.highpass(audio.sample_rate, cutoff, 4);
+    }
+    if let Some(cutoff) = options.lowpass {
+        filters = filters.lowpass(audio.sample_rate, cutoff, 4);
+    }
+    if let Some(mains) = options.notch {
+        filters = filters.notch(audio.sample_rate, mains, 30.0, 5);
+    }
+    let filtered;
+    let signal = if filters.is_empty() {
+        signal
+    } else {
+        let mut copy = signal.to_vec();
+        filters.apply(&mut copy);
+        filtered = copy;
+        &filtered
+    };
 
+    let mut spectrogram = stft(signal, audio.sample_rate, &options.stft);
     println!(
-        "buf.samples().len() is in the range [{},{}]",
-        min_len, max_len
+        "number of STFT frames: {}, bin width: {}",
+        spectrogram.len(),
+        spectrogram.bin_width()
     );
 
-    (samples, duration)
+    if let Some((estimator, reduction)) = options.denoise {
+        denoise(&mut spectrogram, estimator, reduction);
+    }
+
+    // average over time to better distinguish the signal from noise
+    if let Some(config) = &options.smoothing {
+        smooth(&mut spectrogram, config);
+        if matches!(
+            options.interpolation,
+            PeakInterpolation::Quinn | PeakInterpolation::PhaseVocoder
+        ) {
+            println!("smoothed spectra carry no phase, falling back to parabolic interpolation");
+        }
+    }
+    let sample_duration = spectrogram.hop_duration;
+
+    // every harmonic divided by its order, when they are fitted themselves
+    let mut harmonics = vec![];
+    let mut track = match &options.tracker {
+        Tracker::Peak => track_peaks(&spectrogram, options.band, options.interpolation),
+        Tracker::Viterbi(config) => track_viterbi(&spectrogram, config, options.interpolation),
+        Tracker::Harmonic(config) => {
+            let harmonic = track_fundamental(&spectrogram, config, options.interpolation).map_err(|e| e.to_string())?;
+            for (h, track) in harmonic.harmonics.iter().enumerate() {
+                let confidence = track.iter().map(|point| point.confidence).sum::<f64>();
+                println!(
+                    "harmonic #{} mean confidence: {:.3}",
+                    h + 1,
+                    confidence / track.len() as f64
+                );
+                if options.fit_harmonics {
+                    harmonics.push(harmonic.normalised(h + 1));
+                }
+            }
+            harmonic.fundamental
+        }
+    };
+    if track.is_empty() {
+        return Err("no frequency track: nothing was left to track, check --band and the filters".to_string());
+    }
+    if let Some(config) = &options.kalman {
+        track = kalman_smooth(&track, config);
+        for harmonic in &mut harmonics {
+            *harmonic = kalman_smooth(harmonic, config);
+        }
+    }
+    let mean_confidence = track.iter().map(|point| point.confidence).sum::<f64>() / track.len() as f64;
+    println!("mean track confidence: {:.3}", mean_confidence);
+    if !harmonics.is_empty() {
+        println!("fitting the tracks of {} harmonics", harmonics.len());
+        // the harmonics of a frame one after the other
+        track = harmonics.concat();
+        track.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+    Ok((track, sample_duration))
 }
 
-use rustfft::{num_complex::Complex, FftPlanner};
+// a fit that leaves more than this share of the track's spread unexplained isn't a pass
+const MAX_UNEXPLAINED: f64 = 0.5;
 
-fn transform_data(samples: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
-    let mut planner = FftPlanner::<f32>::new();
-    let fft = planner.plan_fft_forward(samples.first().unwrap().iter().len());
+fn analyse_channel(
+    audio: &DecodedAudio,
+    channel: AudioChannel,
+    signal: &[f64],
+    options: &Options,
+    out_file: &str,
+) -> Result<(), String> {
+    println!("=== channel: {}", channel);
+    let (track, sample_duration) = extract_track(audio, signal, options)?;
 
-    let mut frequencies: Vec<Vec<f64>> = vec![];
-    for sample in samples {
-        let mut buffer: Vec<Complex<f32>> = sample
-            .iter()
-            .map(|f| Complex {
-                re: *f as f32,
-                im: 0.0,
-            })
-            .collect();
+    let observations = Observations {
+        points: track.clone(),
+        tau0: sample_duration,
+    };
+    match estimate_pass(&observations, options.model.speed_of_sound.nominal()) {
+        Some(estimate) => println!(
+            "pass estimate: closest approach at {:.2} s, {:.1} m/s, {:.1} m away, source at {:.1} Hz",
+            estimate.t_closest, estimate.speed, estimate.distance, estimate.source_frequency
+        ),
+        None => println!("no pass recognised in the track, using the default initial guess"),
+    }
 
-        fft.process(&mut buffer);
+    let mut solutions = vec![];
+    let mut backends = vec![];
+    for &trajectory in &options.trajectories {
+        if options.trajectories.len() > 1 {
+            println!("trajectory: {}", trajectory.model().name());
+        }
+        let model = DopplerModel {
+            trajectory,
+            ..options.model
+        };
+        if let Some((solution, backend)) = fit_with_solvers(&track, sample_duration, channel, model, options) {
+            solutions.push(solution);
+            backends.push(backend);
+        }
+    }
+    // the simplest trajectory the track supports
+    let Some(selected) = select(&solutions, track.len(), options.criterion) else {
+        return Err("no model solution: no solver produced a fit".to_string());
+    };
+    if solutions.len() > 1 {
+        for solution in &solutions {
+            let report = solution.report();
+            println!(
+                "{}: objective {:.6e}, {} parameters, {} {:.2}",
+                solution.model().trajectory.model().name(),
+                report.objective,
+                solution.model().dimension(),
+                options.criterion.name(),
+                options.criterion.score(report.objective, track.len(), solution.model().dimension())
+            );
+        }
+        println!("selected trajectory: {}", solutions[selected].model().trajectory.model().name());
+    }
+    let backend = backends[selected];
+    let mut approximation = solutions.swap_remove(selected);
+    let report = approximation.report();
+    let share = unexplained(approximation.model(), &observations, approximation.params());
+    if share > MAX_UNEXPLAINED {
+        let caption = format!("Frequencies chart 7 ({})", channel);
+        if let Err(e) = plot_to(out_file, &track, None, &caption) {
+            eprintln!("failed to plot {}: {}", out_file, e);
+        }
+        return Err(format!(
+            "no model solution: the best fit ({}, rms {}) leaves {:.0} % of the track's spread unexplained, so the track doesn't follow one pass; try another --tracker or a narrower --band",
+            report.solver,
+            report.rms,
+            100.0 * share
+        ));
+    }
+    if !report.success() {
+        println!("warning: {} did not converge ({}), these are its best parameters", report.solver, report.termination);
+    }
+    if let Some(resamples) = options.bootstrap {
+        let solver = backend.solver(options.stopping);
+        approximation.bootstrap(&observations, solver.as_ref(), resamples);
+    }
+    print_uncertainty(&approximation);
+    print_geometry(&approximation, options.orientation);
+    let caption = format!("Frequencies + approximation chart 7 ({})", approximation.channel());
+    if let Err(e) = plot_to(out_file, &track, Some(approximation), &caption) {
+        eprintln!("failed to plot {}: {}", out_file, e);
+    }
+    println!("tau0: {}", sample_duration);
+    Ok(())
+}
 
-        // println!("{}", buffer.len());
-        // for complex_value in buffer {
-        //     println!("{:?}", complex_value);
-        // }
-        frequencies.push(buffer.iter().map(|f| f.norm() as f64).collect());
+// fits `model` with every solver of the options and returns the best fit and its solver
+fn fit_with_solvers(
+    track: &[TrackPoint],
+    sample_duration: f64,
+    channel: AudioChannel,
+    model: DopplerModel,
+    options: &Options,
+) -> Option<(OneDeviceSolution, SolverBackend)> {
+    let mut approximation: Option<(OneDeviceSolution, SolverBackend)> = None;
+    for backend in &options.solvers {
+        let solver = backend.solver(options.stopping);
+        let started = Instant::now();
+        let mut trace = |iterate: &Iterate| {
+            println!(
+                "  #{} objective {:.6e} at {:?}",
+                iterate.iteration, iterate.objective, iterate.params
+            )
+        };
+        let progress: Option<&mut dyn FnMut(&Iterate)> = if options.trace { Some(&mut trace) } else { None };
+        let candidate =
+            one_device_approximation(track.to_vec(), sample_duration, channel, model, solver.as_ref(), progress);
+        let elapsed = started.elapsed();
+        let report = candidate.report();
+        println!(
+            "{}: {} after {} iterations ({}), objective {:.6e}, rms {}, {:.1} ms",
+            report.solver,
+            if report.success() { "succeeded" } else { "failed" },
+            report.iterations,
+            report.termination,
+            report.objective,
+            report.rms,
+            elapsed.as_secs_f64() * 1e3
+        );
+        println!("  {}", format_params(&candidate, None));
+        // plot the best of the compared fits
+        if approximation
+            .as_ref()
+            .is_none_or(|(best, _)| report.objective < best.report().objective)
+        {
+            approximation = Some((candidate, *backend));
+        }
+    }
+    if options.solvers.len() > 1 {
+        if let Some((best, _)) = &approximation {
+            println!("best fit: {}", best.report().solver);
+        }
     }
-    frequencies
+    approximation
 }
 
-fn group_by(spectrogram: &mut Vec<Vec<f64>>, group_size: usize) {
-    let mut spectrogram_groupped = vec![];
-    for start in (0..spectrogram.len()).step_by(group_size) {
-        let mut append = vec![0f64; spectrogram[start].len()];
-        for i in 0..spectrogram[start].len() {
-            for j in 0..group_size {
-                if start + j < spectrogram.len() {
-                    append[i] += spectrogram[start + j][i];
-                }
-            }
+// "name = value unit" for every parameter, with error bars if given
+fn format_params(approximation: &OneDeviceSolution, errors: Option<&[f64]>) -> String {
+    let model = approximation.model();
+    let mut parts = vec![];
+    for (i, (name, unit)) in model.parameter_names().iter().zip(model.units()).enumerate() {
+        let value = approximation.params()[i];
+        parts.push(match errors {
+            Some(errors) => format!("{} = {:.2} ± {:.2} {}", name, value, errors[i], unit),
+            None => format!("{} = {:.2} {}", name, value, unit),
+        });
+    }
+    parts.join(", ")
+}
+
+fn print_uncertainty(approximation: &OneDeviceSolution) {
+    match approximation.uncertainty() {
+        Some(uncertainty) => println!(
+            "{}, corr(d, v) = {:.3}",
+            format_params(approximation, Some(&uncertainty.standard_errors)),
+            uncertainty.correlation_d_v
+        ),
+        None => println!("parameter uncertainty: not available, the fit is degenerate"),
+    }
+    if let Some(bootstrap) = approximation.bootstrap_errors() {
+        println!(
+            "bootstrap over {} resamples: {}",
+            bootstrap.resamples,
+            format_params(approximation, Some(&bootstrap.standard_errors))
+        );
+    }
+    if approximation.model().formula != Formula::Legacy {
+        println!("x0 = {:.2} m", approximation.x0());
+    }
+    println!(
+        "speed of sound: {:.2} m/s ({})",
+        approximation.speed_of_sound(),
+        if approximation.model().speed_of_sound.is_fitted() { "fitted" } else { "fixed" }
+    );
+    if approximation.model().formula != Formula::Legacy {
+        println!(
+            "wind: {:.2} m/s ({}), ground speed {:.2} m/s, airspeed {:.2} m/s",
+            approximation.wind(),
+            if approximation.model().wind.is_fitted() { "fitted" } else { "fixed" },
+            approximation.speed(),
+            approximation.airspeed()
+        );
+    }
+}
+
+fn print_geometry(approximation: &OneDeviceSolution, orientation: Option<Orientation>) {
+    let t_closest = approximation.closest_approach();
+    let closest = approximation.position(t_closest);
+    if approximation.model().trajectory.separates_altitude() {
+        println!(
+            "closest approach at {:.2} s: ground offset {:.2} m, altitude {:.2} m",
+            t_closest,
+            closest[1].abs(),
+            closest[2]
+        );
+    }
+    let Some(orientation) = orientation else {
+        return;
+    };
+    let format_enu = |[east, north, up]: [f64; 3]| format!("E {:.1} m, N {:.1} m, U {:.1} m", east, north, up);
+    println!(
+        "heading {:.0}°, microphone {} of the track",
+        orientation.heading,
+        match orientation.side {
+            Side::Left => "left",
+            Side::Right => "right",
+        }
+    );
+    println!("  at t = 0: {}", format_enu(approximation.enu_position(&orientation, 0.0)));
+    println!("  closest approach: {}", format_enu(orientation.to_enu(closest)));
+}
+
+// decodes a file and picks the channel to analyse, reporting failures
+fn load_channel(file: &str, options: &Options) -> Option<(DecodedAudio, AudioChannel, Vec<f64>)> {
+    println!("=== file: {}", file);
+    let audio = match load_audio(file) {
+        Ok(audio) => audio,
+        Err(e) => {
+            eprintln!("{}: {}", file, e);
+            return None;
+        }
+    };
+    println!(
+        "sample rate: {} Hz, channels: {}, duration (seconds): {:.2}",
+        audio.sample_rate, audio.channels, audio.duration
+    );
+    match audio.select_channels(options.channels) {
+        Ok(mut selected) => {
+            let (channel, signal) = selected.swap_remove(0);
+            Some((audio, channel, signal))
+        }
+        Err(e) => {
+            eprintln!("{}: {}", file, e);
+            None
         }
-        spectrogram_groupped.push(append);
     }
-    *spectrogram = spectrogram_groupped;
 }
 
-fn get_frequencies(spectrogram: &Vec<Vec<f64>>) -> Vec<f64> {
-    let mut frequencies = vec![];
-    for distribution in spectrogram {
-        let mut biggest_impact: usize = 0;
-        let mut biggest_sum = 0f64;
-        for i in 0..distribution.len() {
-            let mut current_sum = 0f64;
-            let sum_step = 5;
-            for j in 0.max(i as i32 - sum_step)..(distribution.len() as i32).min(i as i32 + sum_step) {
-                current_sum += distribution[j as usize];
-            }
-            if current_sum > biggest_sum {
-                biggest_impact = i;
-                biggest_sum = current_sum;
+// estimates the clock of every file against the first and writes the offset table
+fn align_files(options: &Options, table_file: &str, config: &AlignmentConfig) -> ExitCode {
+    let mut signals = vec![];
+    for file in &options.files {
+        let Some((audio, _, signal)) = load_channel(file, options) else {
+            return ExitCode::FAILURE;
+        };
+        signals.push((signal, audio.sample_rate));
+    }
+
+    let reference = (signals[0].0.as_slice(), signals[0].1);
+    let mut table = OffsetTable::default();
+    table.entries.push((options.files[0].clone(), ClockOffset::default()));
+    for (file, (signal, rate)) in options.files.iter().zip(&signals).skip(1) {
+        match estimate_offset(reference, (signal, *rate), config) {
+            Some(clock) => table.entries.push((file.clone(), clock)),
+            None => {
+                eprintln!("{}: nothing in common with {} within {} s", file, options.files[0], config.max_offset);
+                return ExitCode::FAILURE;
             }
         }
-        frequencies.push(biggest_impact as f64);
     }
-    frequencies
+
+    println!("=== clocks against {}", options.files[0]);
+    print!("{}", table);
+    let durations: Vec<(ClockOffset, f64)> = table
+        .entries
+        .iter()
+        .zip(&signals)
+        .map(|((_, clock), (signal, rate))| (*clock, signal.len() as f64 / *rate as f64))
+        .collect();
+    match common_span(&durations) {
+        Some((start, end)) => println!("all files overlap from {:.2} s to {:.2} s of {}", start, end, options.files[0]),
+        None => println!("the files don't all overlap"),
+    }
+    if let Err(e) = std::fs::write(table_file, table.to_string()) {
+        eprintln!("{}: {}", table_file, e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
 }
 
-pub struct OneDeviceSolution {
-    x0: f64,
-    d: f64,
-    v0: f64,
-    tau0: f64,
+// reads the offset table and cuts every signal to the span all of them cover, on the clock of the
+// first file; returns where that span starts
+fn apply_offsets(table_file: &str, files: &[String], signals: &mut [(Vec<f64>, u32)]) -> Result<f64, String> {
+    let text = std::fs::read_to_string(table_file).map_err(|e| format!("{}: {}", table_file, e))?;
+    let table = OffsetTable::parse(&text).map_err(|e| format!("{}: {}", table_file, e))?;
+    let clocks = files
+        .iter()
+        .map(|file| table.get(file).ok_or_else(|| format!("{}: no offset for {}", table_file, file)))
+        .collect::<Result<Vec<ClockOffset>, String>>()?;
+    let durations: Vec<(ClockOffset, f64)> = clocks
+        .iter()
+        .zip(signals.iter())
+        .map(|(clock, (signal, rate))| (*clock, signal.len() as f64 / *rate as f64))
+        .collect();
+    let (start, end) = common_span(&durations).ok_or_else(|| format!("{}: the files don't all overlap", table_file))?;
+    for (clock, (signal, rate)) in clocks.iter().zip(signals.iter_mut()) {
+        *signal = on_reference_clock(signal, *rate, clock, start, end - start);
+    }
+    Ok(start)
 }
 
-fn main() {
-    // Get command line arguments.
-    let args: Vec<String> = env::args().collect();
+// tracks every file and fits one track shared by their microphones
+fn analyse_array(options: &Options) -> ExitCode {
+    if let Some((table_file, config)) = &options.align {
+        return align_files(options, table_file, config);
+    }
+    let mut loaded = vec![];
+    let mut signals = vec![];
+    for file in &options.files {
+        let Some((audio, channel, signal)) = load_channel(file, options) else {
+            return ExitCode::FAILURE;
+        };
+        signals.push((signal, audio.sample_rate));
+        loaded.push((audio, channel));
+    }
+    if let Some(table_file) = &options.offsets {
+        match apply_offsets(table_file, &options.files, &mut signals) {
+            Ok(start) => println!("=== times count from {:.3} s into {}", start, options.files[0]),
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
 
-    let (samples, duration) = decode_image(&args[1]);
-    println!("number of vectors of samples: {}", samples.len());
-    let mut sample_duration = duration / f64::from(samples.len() as u32);
+    let mut recordings = vec![];
+    for ((((audio, channel), (signal, _)), &microphone), file) in
+        loaded.iter().zip(&signals).zip(&options.microphones).zip(&options.files)
+    {
+        let (track, sample_duration) = match extract_track(audio, signal, options) {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                eprintln!("{} ({}): {}", file, channel, e);
+                return ExitCode::FAILURE;
+            }
+        };
+        recordings.push(Recording {
+            observations: Observations {
+                points: track,
+                tau0: sample_duration,
+            },
+            microphone,
+            channel: *channel,
+        });
+    }
 
-    let mut spectrogram = transform_data(samples);
+    // altitude:<m> is above the microphone, as with one file: the first one here, while the
+    // shared track is placed in the frame of the microphone positions
+    let altitude = match options.trajectories[..] {
+        [Trajectory::KnownAltitude(known)] => Altitude::Known(options.microphones[0][2] + known.altitude),
+        _ => Altitude::Fitted,
+    };
+    let model = ArrayModel::new(options.model, altitude);
+    let started = Instant::now();
+    let Some(mut solution) = multi_device_approximation(recordings, vec![], model, &options.stopping) else {
+        return ExitCode::FAILURE;
+    };
+    if let Some(config) = &options.tdoa {
+        // the track of the Doppler curves tells GCC-PHAT where to look
+        let guide = |first: usize, second: usize, t: f64| solution.predicted_delay(first, second, t);
+        let signals: Vec<(&[f64], u32)> = signals.iter().map(|(signal, rate)| (signal.as_slice(), *rate)).collect();
+        let delays = frame_delays(&signals, &options.microphones, config, Some(&guide));
+        println!("TDOA: {} frame delays between {} recordings", delays.len(), signals.len());
+        let recordings = solution.recordings().to_vec();
+        match multi_device_approximation(recordings, delays, model, &options.stopping) {
+            Some(combined) => solution = combined,
+            None => return ExitCode::FAILURE,
+        }
+    }
+    let elapsed = started.elapsed();
 
-    // Nyquist–Shannon theorem(truncate in half)
-    for sample in &mut spectrogram {
-        sample.truncate(sample.len() / 2);
+    println!("=== joint fit of {} microphones", options.files.len());
+    let report = solution.report();
+    println!(
+        "{}: {} after {} iterations ({}), objective {:.6e}, rms {}, {:.1} ms",
+        report.solver,
+        if report.success() { "succeeded" } else { "failed" },
+        report.iterations,
+        report.termination,
+        report.objective,
+        report.rms,
+        elapsed.as_secs_f64() * 1e3
+    );
+    let params: Vec<String> = model
+        .parameter_names()
+        .iter()
+        .zip(model.units())
+        .zip(solution.params())
+        .map(|((name, unit), value)| format!("{} = {:.2} {}", name, value, unit))
+        .collect();
+    println!("  {}", params.join(", "));
+    println!(
+        "heading {:.1}°, ground speed {:.2} m/s, airspeed {:.2} m/s, source at {:.2} Hz",
+        solution.heading(),
+        solution.speed(),
+        solution.airspeed(),
+        solution.source_frequency()
+    );
+    println!(
+        "speed of sound: {:.2} m/s ({}), wind: {:.2} m/s ({})",
+        solution.speed_of_sound(),
+        if model.doppler.speed_of_sound.is_fitted() { "fitted" } else { "fixed" },
+        solution.wind(),
+        if model.doppler.wind.is_fitted() { "fitted" } else { "fixed" }
+    );
+    let format_enu = |[east, north, up]: [f64; 3]| format!("E {:.1} m, N {:.1} m, U {:.1} m", east, north, up);
+    println!("  at t = 0: {}", format_enu(solution.position(0.0)));
+    if options.tdoa.is_some() {
+        print_tdoa(&solution, altitude);
     }
 
-    // group and accumulate to better distinguish the signal from noise
-    let group_size = 10usize;
-    group_by(&mut spectrogram, group_size);
-    sample_duration *= f64::from(group_size as u32);
+    for (i, file) in options.files.iter().enumerate() {
+        let device = solution.device(i);
+        let t_closest = device.closest_approach();
+        println!(
+            "microphone {} ({}): closest approach at {:.2} s, {:.1} m away at {}, rms {}",
+            i,
+            file,
+            t_closest,
+            device.distance(),
+            format_enu(solution.position(t_closest)),
+            device.report().rms
+        );
+        let track = &solution.recordings()[i].observations.points;
+        let out_file = format!("plotters-doc-data/frequency-chart-with-approx-7-mic{}.png", i);
+        let caption = format!("Frequencies + approximation chart 7 (microphone {})", i);
+        if let Err(e) = plot_to(&out_file, track, Some(device), &caption) {
+            eprintln!("failed to plot {}: {}", out_file, e);
+        }
+    }
+    ExitCode::SUCCESS
+}
 
-    // plot(total).unwrap();
-    // gif_plots(spectrogram).unwrap();
+// how well the fitted track explains the measured delays, and where they alone put the source
+fn print_tdoa(solution: &MultiDeviceSolution, altitude: Altitude) {
+    let delays = solution.delays();
+    if delays.is_empty() {
+        return;
+    }
+    let squares = delays
+        .iter()
+        .map(|delay| (solution.predicted_delay(delay.first, delay.second, delay.t) - delay.delay).powi(2))
+        .sum::<f64>();
+    println!(
+        "delays: rms {:.2} ms from the track, mean coherence {:.2}",
+        (squares / delays.len() as f64).sqrt() * 1e3,
+        delays.iter().map(|delay| delay.coherence).sum::<f64>() / delays.len() as f64
+    );
 
-    let frequencies = get_frequencies(&spectrogram);
-    
-    // example_usage();
-    let approximation = one_device_approximation(frequencies.clone(), sample_duration);
-    plot(frequencies, Some(approximation), "Frequencies + approximation chart 7").unwrap();
-    println!("tau0: {}", sample_duration);
-    
-    /* FFT tests
-    let mut planner = FftPlanner::<f32>::new();
-    let fft = planner.plan_fft_forward(8);
+    let microphones: Vec<[f64; 3]> = solution.recordings().iter().map(|recording| recording.microphone).collect();
+    let mut times: Vec<f64> = delays.iter().map(|delay| delay.t).collect();
+    times.sort_by(f64::total_cmp);
+    times.dedup();
+    let mut misses = vec![];
+    for &t in &times {
+        let frame: Vec<Delay> = delays.iter().filter(|delay| delay.t == t).copied().collect();
+        // started on the track, which picks the right crossing of the hyperbolas
+        let start = solution.position(t);
+        if let Some(fix) = multilaterate(&frame, &microphones, solution.speed_of_sound(), altitude, start) {
+            misses.push(solution.distance_from_track(&fix.position));
+        }
+    }
+    if misses.is_empty() {
+        // one baseline still gives the angle to the source
+        println!("multilateration: too few microphones heard together to place the source");
+        for i in 0..microphones.len() {
+            for j in i + 1..microphones.len() {
+                let clearest = delays
+                    .iter()
+                    .filter(|delay| delay.first == i && delay.second == j)
+                    .max_by(|a, b| a.coherence.total_cmp(&b.coherence));
+                if let Some(delay) = clearest {
+                    println!(
+                        "  at {:.2} s the source is {:.1}° off the baseline from microphone {} to {}",
+                        delay.t,
+                        bearing(delay, &microphones, solution.speed_of_sound()),
+                        i,
+                        j
+                    );
+                }
+            }
+        }
+        return;
+    }
+    misses.sort_by(f64::total_cmp);
+    println!(
+        "multilateration: {} of {} frames located, median {:.1} m from the track",
+        misses.len(),
+        times.len(),
+        misses[misses.len() / 2]
+    );
+}
 
-    let mut buffer: Vec<Complex<f32>> = vec![
-        Complex { re: 0.0, im: 0.0 },
-        Complex { re: -1.0, im: 0.0 },
-        Complex { re: 0.0, im: 0.0 },
-        Complex { re: 1.0, im: 0.0 },
-        Complex { re: 0.0, im: 0.0 },
-        Complex { re: -1.0, im: 0.0 },
-        Complex { re: 0.0, im: 0.0 },
-        Complex { re: 1.0, im: 0.0 },
-    ];
+fn main() -> ExitCode {
+    // Get command line arguments.
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    fft.process(&mut buffer);
+    if options.files.len() > 1 {
+        return analyse_array(&options);
+    }
+    let file = &options.files[0];
+    let audio = match load_audio(file) {
+        Ok(audio) => audio,
+        Err(e) => {
+            eprintln!("{}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!(
+        "sample rate: {} Hz, channels: {}, duration (seconds): {:.2}",
+        audio.sample_rate, audio.channels, audio.duration
+    );
 
-    for complex_value in buffer {
-        println!("{:?}", complex_value);
+    let signals = match audio.select_channels(options.channels) {
+        Ok(signals) => signals,
+        Err(e) => {
+            eprintln!("{}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut solved = true;
+    for (channel, signal) in &signals {
+        // Every channel gets its own chart when several are analysed
+        let out_file = if signals.len() > 1 {
+            format!("plotters-doc-data/frequency-chart-with-approx-7-{}.png", channel)
+        } else {
+            OUT_FILE_NAME.to_string()
+        };
+        if let Err(e) = analyse_channel(&audio, *channel, signal, &options, &out_file) {
+            eprintln!("{} ({}): {}", file, channel, e);
+            solved = false;
+        }
     }
-    */
 
+    if solved {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
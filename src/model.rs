@@ -0,0 +1,635 @@
+use crate::guess::PassEstimate;
+use crate::solver::Observations;
+use crate::sound::{SpeedOfSound, Wind};
+use crate::tracking::TrackPoint;
+use crate::trajectory::{State, Trajectory};
+
+/// Which formula relates the pass geometry to the heard frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Formula {
+    /// The original formulation with parameters (x0, d, v0):
+    /// nu = C / (tau0*C + A - B), where A and B are the distances to the source one frame apart.
+    /// The rest frequency is implicitly 1/tau0, so it is tied to the STFT hop. Kept so that old
+    /// results stay comparable.
+    Legacy,
+    /// Moving source, stationary observer, with parameters f0 and those of the trajectory, for a
+    /// straight one (f0, d, v, t_closest): f = f0 * C / (C + v_r), v_r being the radial speed of
+    /// the source at time t. The position along the track at t = 0 is x0 = -v * t_closest. A
+    /// wind w along the track makes the sound travel at C - w * u_x along the line of sight,
+    /// u_x being the cosine between it and the track, which takes the place of C; v is then the
+    /// ground speed.
+    ///
+    /// Without the retarded-time correction, the source is taken where it is at the time of
+    /// hearing. With it, it is taken where it was when it emitted what is heard at t, i.e. at
+    /// t_e with t = t_e + r(t_e) / n(t_e), and t_closest is the emission time of closest
+    /// approach.
+    #[default]
+    Classical,
+}
+
+/// A formula together with the medium the sound travels through.
+///
+/// A fitted speed of sound is appended to the parameters of the formula as `c`, then a fitted
+/// wind as `w`. With a single microphone the speed of sound is degenerate with the geometry:
+/// scaling c, v and d by the same factor leaves the heard frequency unchanged. The wind only
+/// shows in the asymmetry between approach and recession. So besides their bounds both get a
+/// Gaussian prior centred on the nominal value with a quarter of the bounds' width as standard
+/// deviation, and their uncertainty carries over into those of v and d.
+///
+/// The legacy formula ignores the trajectory, the wind and the retarded-time correction.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DopplerModel {
+    pub formula: Formula,
+    /// Path the classical formula moves the source along.
+    pub trajectory: Trajectory,
+    pub speed_of_sound: SpeedOfSound,
+    pub wind: Wind,
+    /// Solve for the emission time of every observation instead of using the time of hearing.
+    pub retarded_time: bool,
+}
+
+const LEGACY_PARAMETERS: [&str; 3] = ["x0", "d", "v0"];
+const LEGACY_UNITS: [&str; 3] = ["m", "m", "m/s"];
+
+// (x0, d, v0), when the track shows no recognisable pass
+const LEGACY_GUESS: [f64; 3] = [200.0, 40.0, 50.0];
+// (distance, speed) when the track shows no recognisable pass
+const CLASSICAL_GUESS: [f64; 2] = [40.0, 50.0];
+
+impl DopplerModel {
+    pub fn new(formula: Formula, speed_of_sound: SpeedOfSound, wind: Wind) -> Self {
+        DopplerModel {
+            formula,
+            trajectory: Trajectory::default(),
+            speed_of_sound,
+            wind,
+            retarded_time: false,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.formula {
+            Formula::Legacy => "legacy",
+            Formula::Classical => "classical",
+        }
+    }
+
+    pub fn parameter_names(&self) -> Vec<&'static str> {
+        let mut names = match self.formula {
+            Formula::Legacy => LEGACY_PARAMETERS.to_vec(),
+            Formula::Classical => [&["f0"], self.trajectory.model().parameter_names()].concat(),
+        };
+        if self.speed_of_sound.is_fitted() {
+            names.push("c");
+        }
+        if self.fits_wind() {
+            names.push("w");
+        }
+        names
+    }
+
+    pub fn units(&self) -> Vec<&'static str> {
+        let mut units = match self.formula {
+            Formula::Legacy => LEGACY_UNITS.to_vec(),
+            Formula::Classical => [&["Hz"], self.trajectory.model().units()].concat(),
+        };
+        if self.speed_of_sound.is_fitted() {
+            units.push("m/s");
+        }
+        if self.fits_wind() {
+            units.push("m/s");
+        }
+        units
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.parameter_names().len()
+    }
+
+    /// Index of the miss distance, or of what the trajectory has in its place, in the parameter
+    /// vector.
+    pub fn distance_index(&self) -> usize {
+        1
+    }
+
+    /// Miss distance (m).
+    pub fn distance(&self, params: &[f64]) -> f64 {
+        match self.formula {
+            Formula::Legacy => params[1].abs(),
+            Formula::Classical => self.trajectory.model().distance(&params[1..]),
+        }
+    }
+
+    /// Index of the source speed in the parameter vector, at closest approach if it changes.
+    pub fn speed_index(&self) -> usize {
+        2
+    }
+
+    // number of parameters of the formula alone
+    fn formula_dimension(&self) -> usize {
+        match self.formula {
+            Formula::Legacy => LEGACY_PARAMETERS.len(),
+            Formula::Classical => 1 + self.trajectory.model().parameter_names().len(),
+        }
+    }
+
+    // whether the wind is a parameter; the legacy formula has no use for it
+    fn fits_wind(&self) -> bool {
+        self.formula == Formula::Classical && self.wind.is_fitted()
+    }
+
+    // index of a fitted wind, after a fitted speed of sound
+    fn wind_index(&self) -> usize {
+        self.formula_dimension() + self.speed_of_sound.is_fitted() as usize
+    }
+
+    /// Speed of sound the parameters imply: the fitted one, or the fixed value.
+    pub fn speed_of_sound(&self, params: &[f64]) -> f64 {
+        match self.speed_of_sound {
+            SpeedOfSound::Fixed(c) => c,
+            SpeedOfSound::Fitted { .. } => params[self.formula_dimension()],
+        }
+    }
+
+    /// Wind along the track the parameters imply (m/s); always calm for the legacy formula.
+    pub fn wind(&self, params: &[f64]) -> f64 {
+        match self.wind {
+            _ if self.formula == Formula::Legacy => 0.0,
+            Wind::Fixed(w) => w,
+            Wind::Fitted { .. } => params[self.wind_index()],
+        }
+    }
+
+    /// Speed of the source relative to the air (m/s), the ground speed minus the wind.
+    pub fn airspeed(&self, params: &[f64]) -> f64 {
+        params[self.speed_index()] - self.wind(params)
+    }
+
+    /// Residuals of the priors on a fitted speed of sound and wind, as (parameter index,
+    /// residual, derivative of the residual over that parameter). Empty when nothing is fitted.
+    pub fn priors(&self, params: &[f64]) -> Vec<(usize, f64, f64)> {
+        let mut priors = vec![];
+        if let SpeedOfSound::Fitted { min, max } = self.speed_of_sound {
+            let index = self.formula_dimension();
+            let sigma = (max - min) / 4.0;
+            priors.push((index, (params[index] - self.speed_of_sound.nominal()) / sigma, 1.0 / sigma));
+        }
+        if let (true, Wind::Fitted { min, max }) = (self.fits_wind(), self.wind) {
+            let index = self.wind_index();
+            let sigma = (max - min) / 4.0;
+            priors.push((index, (params[index] - self.wind.nominal()) / sigma, 1.0 / sigma));
+        }
+        priors
+    }
+
+    /// Keeps a changing speed below the Doppler pole over the span of the track `points`, see
+    /// `TrajectoryModel::limit_speed`.
+    pub fn limit_speed(&self, params: &mut [f64], points: &[TrackPoint]) {
+        if let (Formula::Classical, Some(first), Some(last)) = (self.formula, points.first(), points.last()) {
+            // the trajectory parameters follow the source frequency
+            self.trajectory
+                .model()
+                .limit_speed(&mut params[1..], self.max_speed(), first.time, last.time);
+        }
+    }
+
+    /// Moves a fitted speed of sound and wind back into their bounds, for solvers that have
+    /// none of their own.
+    pub fn clamp_nuisance(&self, params: &mut [f64]) {
+        if let SpeedOfSound::Fitted { min, max } = self.speed_of_sound {
+            let index = self.formula_dimension();
+            params[index] = params[index].clamp(min, max);
+        }
+        if let (true, Wind::Fitted { min, max }) = (self.fits_wind(), self.wind) {
+            let index = self.wind_index();
+            params[index] = params[index].clamp(min, max);
+        }
+    }
+
+    /// Heard frequency at time t.
+    pub fn frequency(&self, params: &[f64], tau0: f64, t: f64) -> f64 {
+        self.gradient(params, tau0, t).0
+    }
+
+    /// Heard frequency at time t and its partial derivatives over the parameters.
+    pub fn gradient(&self, params: &[f64], tau0: f64, t: f64) -> (f64, Vec<f64>) {
+        let c = self.speed_of_sound(params);
+        let (f, mut gradient, d_c, d_w) = match self.formula {
+            Formula::Legacy => {
+                let (nu, gradient, d_c) = legacy(params, tau0, t, c);
+                (nu, gradient, d_c, 0.0)
+            }
+            Formula::Classical => self.moving_source(params, t, c, self.wind(params)),
+        };
+        if self.speed_of_sound.is_fitted() {
+            gradient.push(d_c);
+        }
+        if self.fits_wind() {
+            gradient.push(d_w);
+        }
+        (f, gradient)
+    }
+
+    /// Position of the source along its track at t = 0.
+    pub fn x0(&self, params: &[f64]) -> f64 {
+        self.position(params, 0.0)[0]
+    }
+
+    /// Position of the source at time t in the track frame of `trajectory::State`; the legacy
+    /// track is level with the microphone.
+    pub fn position(&self, params: &[f64], t: f64) -> [f64; 3] {
+        match self.formula {
+            Formula::Legacy => [params[0] + params[2] * t, params[1], 0.0],
+            Formula::Classical => self.trajectory.model().state(&params[1..], t).position,
+        }
+    }
+
+    /// Time of closest approach (s), when the source crosses x = 0.
+    pub fn closest_approach(&self, params: &[f64]) -> f64 {
+        match self.formula {
+            Formula::Legacy => -params[0] / params[2],
+            Formula::Classical => params[3],
+        }
+    }
+
+    /// Fitted rest frequency of the source; the legacy model has none.
+    pub fn source_frequency(&self, params: &[f64]) -> Option<f64> {
+        match self.formula {
+            Formula::Legacy => None,
+            Formula::Classical => Some(params[0]),
+        }
+    }
+
+    /// Parameters of the pass described by `estimate`.
+    pub fn from_estimate(&self, estimate: &PassEstimate) -> Vec<f64> {
+        let params = match self.formula {
+            Formula::Legacy => vec![-estimate.speed * estimate.t_closest, estimate.distance, estimate.speed],
+            Formula::Classical => {
+                let mut estimate = *estimate;
+                // the track shows when closest approach is heard, not when it happens
+                if self.retarded_time {
+                    estimate.t_closest -= estimate.distance / self.speed_of_sound.nominal();
+                }
+                [vec![estimate.source_frequency], self.trajectory.model().guess(&estimate)].concat()
+            }
+        };
+        self.with_nuisance(params)
+    }
+
+    /// Starting point when the track shows no recognisable pass.
+    pub fn default_guess(&self, observations: &Observations) -> Vec<f64> {
+        let params = match self.formula {
+            Formula::Legacy => LEGACY_GUESS.to_vec(),
+            Formula::Classical => {
+                // the middle of the track, heard at the median frequency
+                let mut frequencies: Vec<f64> = observations.points.iter().map(|p| p.frequency.0).collect();
+                frequencies.sort_by(f64::total_cmp);
+                let median = frequencies.get(frequencies.len() / 2).copied().unwrap_or(100.0);
+                let estimate = PassEstimate {
+                    t_closest: observations.points.first().map_or(0.0, |point| point.time) / 2.0 + observations.end() / 2.0,
+                    f_approach: median,
+                    f_recede: median,
+                    speed: CLASSICAL_GUESS[1],
+                    source_frequency: median,
+                    distance: CLASSICAL_GUESS[0],
+                };
+                return self.from_estimate(&estimate);
+            }
+        };
+        self.with_nuisance(params)
+    }
+
+    // appends the nominal speed of sound and wind if they are fitted
+    fn with_nuisance(&self, mut params: Vec<f64>) -> Vec<f64> {
+        if self.speed_of_sound.is_fitted() {
+            params.push(self.speed_of_sound.nominal());
+        }
+        if self.fits_wind() {
+            params.push(self.wind.nominal());
+        }
+        params
+    }
+
+    // appends the bounds of a fitted speed of sound and wind
+    fn with_nuisance_ranges(&self, mut ranges: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+        if let SpeedOfSound::Fitted { min, max } = self.speed_of_sound {
+            ranges.push((min, max));
+        }
+        if let (true, Wind::Fitted { min, max }) = (self.fits_wind(), self.wind) {
+            ranges.push((min, max));
+        }
+        ranges
+    }
+
+    // fastest ground speed that stays subsonic against the slowest sound and strongest headwind
+    fn max_speed(&self) -> f64 {
+        0.99 * (self.speed_of_sound.min() + self.wind.min())
+    }
+
+    /// Box the gomez solvers are confined to.
+    pub fn domain(&self) -> (Vec<f64>, Vec<f64>) {
+        let ranges = match self.formula {
+            Formula::Legacy => vec![(-100000.0, 100000.0), (0.0, 100000.0), (-100000.0, 100000.0)],
+            // subsonic, otherwise the heard frequency has a pole
+            Formula::Classical => [vec![(0.0, 100000.0)], self.trajectory.model().domain(self.max_speed())].concat(),
+        };
+        self.with_nuisance_ranges(ranges).into_iter().unzip()
+    }
+
+    /// Plausible ranges for the grid search.
+    pub fn search_ranges(&self, observations: &Observations) -> Vec<(f64, f64)> {
+        let ranges = match self.formula {
+            Formula::Legacy => vec![(-10000.0, 10000.0), (1.0, 2000.0), (-340.0, 340.0)],
+            Formula::Classical => {
+                let (lo, hi) = observations
+                    .points
+                    .iter()
+                    .map(|p| p.frequency.0)
+                    .fold((f64::INFINITY, 0.0f64), |(lo, hi), f| (lo.min(f), hi.max(f)));
+                let duration = observations.end();
+                let trajectory = self.trajectory.model().search_ranges(self.max_speed(), duration);
+                [vec![(lo.min(hi), hi.max(lo))], trajectory].concat()
+            }
+        };
+        self.with_nuisance_ranges(ranges)
+    }
+
+    /// Brings equivalent solutions to one form: the legacy formula only depends on d^2, the
+    /// classical one is the same mirrored across the track, and along it unless a fixed wind
+    /// tells the directions apart.
+    pub fn canonical(&self, params: &[f64]) -> Vec<f64> {
+        let mut params = params.to_vec();
+        match self.formula {
+            Formula::Legacy => params[1] = params[1].abs(),
+            Formula::Classical => {
+                let trajectory = self.trajectory.model();
+                trajectory.canonical(&mut params[1..]);
+                if self.fits_wind() {
+                    if trajectory.reverse(&mut params[1..]) {
+                        params[self.wind_index()] *= -1.0;
+                    }
+                } else if self.wind.nominal() == 0.0 {
+                    trajectory.reverse(&mut params[1..]);
+                }
+            }
+        }
+        params
+    }
+
+    // the classical formula along the trajectory, at the time of hearing t or at the emission
+    // time t_e solving t = t_e + q(t_e), q the travel time, if correcting for it; then the
+    // gradient adds df/dt_e * dt_e/dp, dt_e/dp = -(dq/dp) / (1 + dq/dt_e)
+    // returns f, its gradient over (f0, trajectory...) and its derivatives over c and w
+    fn moving_source(&self, params: &[f64], t: f64, c: f64, w: f64) -> (f64, Vec<f64>, f64, f64) {
+        let (f0, trajectory) = (params[0], self.trajectory.model());
+        let trajectory_params = &params[1..];
+
+        let mut t_e = t;
+        if self.retarded_time {
+            // start from the source's position at the time of hearing
+            t_e -= travel(&trajectory.state(trajectory_params, t), c, w).time;
+            for _ in 0..RETARDED_TIME_STEPS {
+                let state = trajectory.state(trajectory_params, t_e);
+                let travel = travel(&state, c, w);
+                let step = (t_e + travel.time - t) / (1.0 + dot(&travel.d_position, &state.velocity));
+                t_e -= step;
+                if step.abs() <= 1e-12 * (1.0 + t_e.abs()) {
+                    break;
+                }
+            }
+        }
+
+        let state = trajectory.state(trajectory_params, t_e);
+        let heard = heard(f0, &state, c, w);
+        let mut gradient = vec![heard.d_f0];
+        gradient.extend(
+            state
+                .d_position
+                .iter()
+                .zip(&state.d_velocity)
+                .map(|(d_position, d_velocity)| dot(&heard.d_position, d_position) + dot(&heard.d_velocity, d_velocity)),
+        );
+        let (mut d_c, mut d_w) = (heard.d_c, heard.d_w);
+        if self.retarded_time {
+            let travel = travel(&state, c, w);
+            let f_t = dot(&heard.d_position, &state.velocity) + dot(&heard.d_velocity, &state.acceleration);
+            let denominator = 1.0 + dot(&travel.d_position, &state.velocity);
+            for (value, d_position) in gradient[1..].iter_mut().zip(&state.d_position) {
+                *value -= f_t * dot(&travel.d_position, d_position) / denominator;
+            }
+            d_c -= f_t * travel.d_c / denominator;
+            d_w -= f_t * travel.d_w / denominator;
+        }
+        (heard.f, gradient, d_c, d_w)
+    }
+}
+
+//   nu = c / D,  D = tau0*c + A - B
+//   A = sqrt(d^2 + alpha^2), alpha = x0 + v0*(t + tau0)
+//   B = sqrt(d^2 + beta^2),  beta  = x0 + v0*t
+// returns nu, its gradient over (x0, d, v0) and its derivative over c
+fn legacy(params: &[f64], tau0: f64, t: f64, c: f64) -> (f64, Vec<f64>, f64) {
+    let (x0, d, v0) = (params[0], params[1], params[2]);
+    let alpha = x0 + v0 * t + v0 * tau0;
+    let beta = x0 + v0 * t;
+    #[allow(non_snake_case)]
+    let A = (d.powi(2) + alpha.powi(2)).sqrt().max(f64::MIN_POSITIVE);
+    #[allow(non_snake_case)]
+    let B = (d.powi(2) + beta.powi(2)).sqrt().max(f64::MIN_POSITIVE);
+    #[allow(non_snake_case)]
+    let D = tau0 * c + A - B;
+    let nu = c / D;
+
+    // d(nu)/dp = -c / D^2 * dD/dp
+    let factor = -c / (D * D);
+    let d_x0 = alpha / A - beta / B;
+    let d_d = d / A - d / B;
+    let d_v0 = alpha / A * (t + tau0) - beta / B * t;
+    let d_c = (A - B) / (D * D);
+    (nu, vec![factor * d_x0, factor * d_d, factor * d_v0], d_c)
+}
+
+pub(crate) fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+// heard frequency and its derivatives over the rest frequency, the source's position and
+// velocity, the speed of sound and the wind
+struct Heard {
+    f: f64,
+    d_f0: f64,
+    d_position: [f64; 3],
+    d_velocity: [f64; 3],
+    d_c: f64,
+    d_w: f64,
+}
+
+//   f = f0 * n / (n + b),  n = c - w u_x,  b = V . u,  u = P / r,  r = |P|
+// n is the speed of sound along the line of sight, b the radial speed of the source
+fn heard(f0: f64, state: &State, c: f64, w: f64) -> Heard {
+    let (p, v) = (&state.position, &state.velocity);
+    let r = dot(p, p).sqrt().max(f64::MIN_POSITIVE);
+    let u = [p[0] / r, p[1] / r, p[2] / r];
+    let n = c - w * u[0];
+    let b = dot(v, &u);
+    let denominator = n + b;
+    let squared = denominator.powi(2);
+
+    // df/dn = f0 b / (n + b)^2, df/db = -f0 n / (n + b)^2
+    let f_n = f0 * b / squared;
+    let f_b = -f0 * n / squared;
+    // db/dP = (V - b u) / r, dn/dP = -w (e_x - u_x u) / r
+    let mut d_position = [0.0; 3];
+    for (i, d) in d_position.iter_mut().enumerate() {
+        let e_x = if i == 0 { 1.0 } else { 0.0 };
+        let d_b = (v[i] - b * u[i]) / r;
+        let d_n = -w * (e_x - u[0] * u[i]) / r;
+        *d = f_n * d_n + f_b * d_b;
+    }
+    Heard {
+        f: f0 * n / denominator,
+        d_f0: n / denominator,
+        d_position,
+        d_velocity: [f_b * u[0], f_b * u[1], f_b * u[2]],
+        d_c: f_n,
+        d_w: -f_n * u[0],
+    }
+}
+
+// travel time of the sound to the microphone and its derivatives over the source's position,
+// the speed of sound and the wind
+struct Travel {
+    time: f64,
+    d_position: [f64; 3],
+    d_c: f64,
+    d_w: f64,
+}
+
+//   q = r / n,  dq/dx = (dr/dx - q dn/dx) / n
+fn travel(state: &State, c: f64, w: f64) -> Travel {
+    let p = &state.position;
+    let r = dot(p, p).sqrt().max(f64::MIN_POSITIVE);
+    let u = [p[0] / r, p[1] / r, p[2] / r];
+    let n = c - w * u[0];
+    let q = r / n;
+    let mut d_position = [0.0; 3];
+    for (i, d) in d_position.iter_mut().enumerate() {
+        let e_x = if i == 0 { 1.0 } else { 0.0 };
+        let d_n = -w * (e_x - u[0] * u[i]) / r;
+        *d = (u[i] - q * d_n) / n;
+    }
+    Travel {
+        time: q,
+        d_position,
+        d_c: -q / n,
+        d_w: q * u[0] / n,
+    }
+}
+
+// Newton steps solving for the emission time, far more than the few it takes subsonic
+const RETARDED_TIME_STEPS: usize = 20;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trajectory::{KnownAltitude, KnownOffset};
+
+    // largest relative difference between the gradient and central differences
+    fn gradient_error(model: DopplerModel, params: &[f64]) -> f64 {
+        assert_eq!(params.len(), model.dimension());
+        let mut worst: f64 = 0.0;
+        for t in [1.0, 4.5, 8.0] {
+            let (_, gradient) = model.gradient(params, 0.128, t);
+            for (i, analytic) in gradient.iter().enumerate() {
+                let h = 1e-6 * params[i].abs().max(1.0);
+                let (mut above, mut below) = (params.to_vec(), params.to_vec());
+                above[i] += h;
+                below[i] -= h;
+                let numeric = (model.frequency(&above, 0.128, t) - model.frequency(&below, 0.128, t)) / (2.0 * h);
+                worst = worst.max((numeric - analytic).abs() / numeric.abs().max(1e-3));
+            }
+        }
+        worst
+    }
+
+    #[test]
+    fn gradient_matches_finite_differences() {
+        let classical = DopplerModel::default();
+        let error = gradient_error(classical, &[350.0, 60.0, 45.0, 5.0]);
+        assert!(error < 1e-5, "{:e}", error);
+        let legacy = DopplerModel::new(Formula::Legacy, SpeedOfSound::default(), Wind::default());
+        let error = gradient_error(legacy, &[200.0, 40.0, 50.0]);
+        assert!(error < 1e-5, "{:e}", error);
+    }
+
+    #[test]
+    fn gradient_covers_a_fitted_speed_of_sound() {
+        let model = DopplerModel::new(Formula::Classical, SpeedOfSound::FITTED, Wind::default());
+        let error = gradient_error(model, &[350.0, 60.0, 45.0, 5.0, 340.0]);
+        assert!(error < 1e-5, "{:e}", error);
+    }
+
+    #[test]
+    fn gradient_covers_the_wind() {
+        let fixed = DopplerModel::new(Formula::Classical, SpeedOfSound::default(), Wind::Fixed(-6.0));
+        let error = gradient_error(fixed, &[350.0, 60.0, 45.0, 5.0]);
+        assert!(error < 1e-5, "{:e}", error);
+        let fitted = DopplerModel::new(Formula::Classical, SpeedOfSound::FITTED, Wind::FITTED);
+        let error = gradient_error(fitted, &[350.0, 60.0, 45.0, 5.0, 340.0, 4.0]);
+        assert!(error < 1e-5, "{:e}", error);
+    }
+
+    #[test]
+    fn gradient_covers_the_retarded_time() {
+        for (speed_of_sound, wind, params) in [
+            (SpeedOfSound::default(), Wind::default(), vec![350.0, 60.0, 45.0, 5.0]),
+            (SpeedOfSound::FITTED, Wind::FITTED, vec![350.0, 60.0, 45.0, 5.0, 340.0, 4.0]),
+        ] {
+            let model = DopplerModel {
+                retarded_time: true,
+                ..DopplerModel::new(Formula::Classical, speed_of_sound, wind)
+            };
+            let error = gradient_error(model, &params);
+            assert!(error < 1e-5, "{:?}: {:e}", model, error);
+        }
+    }
+
+    #[test]
+    fn gradient_covers_the_trajectories() {
+        for (trajectory, params) in [
+            (Trajectory::ConstantAcceleration, [350.0, 60.0, 45.0, 5.0, 1.5]),
+            (Trajectory::Turn, [350.0, 60.0, 45.0, 5.0, 0.05]),
+            // below the small angle, where the turn takes its series
+            (Trajectory::Turn, [350.0, 60.0, 45.0, 5.0, 1e-5]),
+            (Trajectory::AltitudeOffset, [350.0, 40.0, 45.0, 5.0, 50.0]),
+        ] {
+            for retarded_time in [false, true] {
+                let model = DopplerModel {
+                    trajectory,
+                    retarded_time,
+                    ..DopplerModel::default()
+                };
+                let error = gradient_error(model, &params);
+                assert!(error < 1e-5, "{:?}, retarded time {}: {:e}", trajectory, retarded_time, error);
+            }
+        }
+    }
+
+    #[test]
+    fn gradient_covers_a_known_altitude_or_offset() {
+        for trajectory in [
+            Trajectory::KnownAltitude(KnownAltitude { altitude: 40.0 }),
+            Trajectory::KnownOffset(KnownOffset { offset: 30.0 }),
+        ] {
+            for retarded_time in [false, true] {
+                let model = DopplerModel {
+                    trajectory,
+                    retarded_time,
+                    ..DopplerModel::new(Formula::Classical, SpeedOfSound::FITTED, Wind::FITTED)
+                };
+                let error = gradient_error(model, &[350.0, 30.0, 45.0, 5.0, 340.0, 4.0]);
+                assert!(error < 1e-5, "{:?}, retarded time {}: {:e}", trajectory, retarded_time, error);
+            }
+        }
+    }
+}
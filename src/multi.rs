@@ -0,0 +1,664 @@
+use std::cell::RefCell;
+use std::f64::consts::{PI, TAU};
+
+use levenberg_marquardt::{LeastSquaresProblem, LevenbergMarquardt};
+use nalgebra::{storage::Owned, DMatrix, DVector, Dyn};
+
+use crate::approx::{one_device_approximation, residual_rms};
+use crate::lma::{termination, weight};
+use crate::loader::AudioChannel;
+use crate::model::{dot, DopplerModel, Formula};
+use crate::solver::{LevenbergMarquardtSolver, Observations, StoppingCriteria};
+use crate::tdoa::{distance, Delay};
+use crate::trajectory::{other_component, Trajectory};
+use crate::{FitReport, Iterate, OneDeviceSolution};
+
+/// One recording of the pass: its track, and where its microphone stood in metres east, north
+/// and up of an origin shared by all recordings. The tracks must share t = 0, the start of every
+/// recording, which `align` can bring about for files that weren't started together.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub observations: Observations,
+    pub microphone: [f64; 3],
+    pub channel: AudioChannel,
+}
+
+/// Height of the shared track above the origin of the microphone positions.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Altitude {
+    /// A level track at this height, m.
+    Known(f64),
+    /// Fitted, which takes microphones at different distances from the track.
+    #[default]
+    Fitted,
+}
+
+/// A straight level track shared by several microphones, in their east/north/up frame.
+///
+/// The parameters are (f0, east, north, [up], heading, v) followed by the fitted speed of sound
+/// and wind of `doppler`: the position at t = 0, the direction of flight in radians clockwise
+/// from north and the ground speed. Every microphone hears the classical straight model of
+/// `doppler` with its own miss distance and time of closest approach, which follow from the
+/// shared track.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ArrayModel {
+    pub doppler: DopplerModel,
+    pub altitude: Altitude,
+}
+
+// parameters of the straight model the array is made of: f0, d, v, t_closest
+const DEVICE_PARAMETERS: usize = 4;
+
+// Newton steps solving for the emission time of a delay, as in the retarded-time model
+const EMISSION_TIME_STEPS: usize = 20;
+
+// per microphone: d and t_closest, with their derivatives over (east, north, up, heading, v)
+struct DeviceGeometry {
+    distance: f64,
+    t_closest: f64,
+    d_distance: [f64; 5],
+    d_t_closest: [f64; 5],
+}
+
+impl ArrayModel {
+    /// `doppler` supplies the speed of sound, wind and retarded-time settings; its formula and
+    /// trajectory are always the classical straight ones.
+    pub fn new(doppler: DopplerModel, altitude: Altitude) -> Self {
+        ArrayModel {
+            doppler: DopplerModel {
+                formula: Formula::Classical,
+                trajectory: Trajectory::Straight,
+                ..doppler
+            },
+            altitude,
+        }
+    }
+
+    fn fits_altitude(&self) -> bool {
+        self.altitude == Altitude::Fitted
+    }
+
+    // number of parameters before the nuisance ones
+    fn geometry_dimension(&self) -> usize {
+        5 + self.fits_altitude() as usize
+    }
+
+    pub fn parameter_names(&self) -> Vec<&'static str> {
+        let mut names = vec!["f0", "east", "north"];
+        if self.fits_altitude() {
+            names.push("up");
+        }
+        names.extend(["heading", "v"]);
+        names.extend(&self.doppler.parameter_names()[DEVICE_PARAMETERS..]);
+        names
+    }
+
+    pub fn units(&self) -> Vec<&'static str> {
+        let mut units = vec!["Hz", "m", "m"];
+        if self.fits_altitude() {
+            units.push("m");
+        }
+        units.extend(["rad", "m/s"]);
+        units.extend(&self.doppler.units()[DEVICE_PARAMETERS..]);
+        units
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.parameter_names().len()
+    }
+
+    // (east, north, up, heading, v)
+    fn track(&self, params: &[f64]) -> [f64; 5] {
+        match self.altitude {
+            Altitude::Known(up) => [params[1], params[2], up, params[3], params[4]],
+            Altitude::Fitted => [params[1], params[2], params[3], params[4], params[5]],
+        }
+    }
+
+    fn heading_index(&self) -> usize {
+        self.geometry_dimension() - 2
+    }
+
+    fn speed_index(&self) -> usize {
+        self.geometry_dimension() - 1
+    }
+
+    /// Position of the source (m east, north, up) at time t.
+    pub fn position(&self, params: &[f64], t: f64) -> [f64; 3] {
+        let [east, north, up, heading, v] = self.track(params);
+        let (sin, cos) = heading.sin_cos();
+        [east + v * t * sin, north + v * t * cos, up]
+    }
+
+    //   a = (P0 - M) . f,  f = (sin heading, cos heading, 0)
+    //   t_closest = -a / v,  d = |P0 - M - a f|
+    fn device_geometry(&self, params: &[f64], microphone: &[f64; 3]) -> DeviceGeometry {
+        let [east, north, up, heading, v] = self.track(params);
+        let (sin, cos) = heading.sin_cos();
+        let relative = [east - microphone[0], north - microphone[1], up - microphone[2]];
+        let along = relative[0] * sin + relative[1] * cos;
+        // da/dheading
+        let turned = relative[0] * cos - relative[1] * sin;
+        let across = [relative[0] - along * sin, relative[1] - along * cos, relative[2]];
+        let distance = (across[0].powi(2) + across[1].powi(2) + across[2].powi(2))
+            .sqrt()
+            .max(f64::MIN_POSITIVE);
+        DeviceGeometry {
+            distance,
+            t_closest: -along / v,
+            d_distance: [
+                across[0] / distance,
+                across[1] / distance,
+                across[2] / distance,
+                -along * turned / distance,
+                0.0,
+            ],
+            d_t_closest: [-sin / v, -cos / v, 0.0, -turned / v, along / (v * v)],
+        }
+    }
+
+    /// Parameters of the straight single-microphone model the microphone at `microphone`
+    /// hears.
+    pub fn device_params(&self, params: &[f64], microphone: &[f64; 3]) -> Vec<f64> {
+        let geometry = self.device_geometry(params, microphone);
+        let v = params[self.speed_index()];
+        let mut device = vec![params[0], geometry.distance, v, geometry.t_closest];
+        device.extend(&params[self.geometry_dimension()..]);
+        device
+    }
+
+    /// Frequency heard at `microphone` at time t and its gradient over the parameters.
+    pub fn gradient(&self, params: &[f64], microphone: &[f64; 3], tau0: f64, t: f64) -> (f64, Vec<f64>) {
+        let geometry = self.device_geometry(params, microphone);
+        let device = self.device_params(params, microphone);
+        let (f, device_gradient) = self.doppler.gradient(&device, tau0, t);
+
+        let mut gradient = vec![0.0; self.dimension()];
+        gradient[0] = device_gradient[0];
+        // (east, north, [up], heading, v) in the parameter vector
+        let columns: Vec<(usize, usize)> = match self.altitude {
+            Altitude::Known(_) => vec![(1, 0), (2, 1), (3, 3), (4, 4)],
+            Altitude::Fitted => vec![(1, 0), (2, 1), (3, 2), (4, 3), (5, 4)],
+        };
+        for (column, axis) in columns {
+            gradient[column] = device_gradient[1] * geometry.d_distance[axis]
+                + device_gradient[3] * geometry.d_t_closest[axis];
+        }
+        gradient[self.speed_index()] += device_gradient[2];
+        let nuisance = self.geometry_dimension();
+        gradient[nuisance..].copy_from_slice(&device_gradient[DEVICE_PARAMETERS..]);
+        (f, gradient)
+    }
+
+    /// How much later the sound `first` hears at time t reaches `second`, and the gradient of
+    /// that delay over the parameters.
+    ///
+    /// Both hear what the source emitted at t_e, with t = t_e + |P(t_e) - first| / c. The sound
+    /// is taken to travel straight at the speed of sound; the wind changes both paths almost
+    /// alike and is left out.
+    pub fn delay(&self, params: &[f64], first: &[f64; 3], second: &[f64; 3], t: f64) -> (f64, Vec<f64>) {
+        let [_, _, _, heading, v] = self.track(params);
+        let (sin, cos) = heading.sin_cos();
+        let velocity = [v * sin, v * cos, 0.0];
+        let c = self.doppler.speed_of_sound(&self.device_params(params, first));
+        let unit = |source: &[f64; 3], microphone: &[f64; 3]| {
+            let range = distance(source, microphone).max(f64::MIN_POSITIVE);
+            (range, [0, 1, 2].map(|axis| (source[axis] - microphone[axis]) / range))
+        };
+
+        let mut t_e = t;
+        for _ in 0..EMISSION_TIME_STEPS {
+            let (range, direction) = unit(&self.position(params, t_e), first);
+            let step = (t_e + range / c - t) / (1.0 + dot(&direction, &velocity) / c);
+            t_e -= step;
+            if step.abs() <= 1e-12 * (1.0 + t_e.abs()) {
+                break;
+            }
+        }
+        let source = self.position(params, t_e);
+        let (first_range, first_direction) = unit(&source, first);
+        let (second_range, second_direction) = unit(&source, second);
+        let delay = (second_range - first_range) / c;
+
+        // D = (r2 - r1) / c at t_e, which itself moves with the parameters:
+        //   dt_e/dp = -(dr1/dp) / c / (1 + u1 . V / c)
+        let difference = [0, 1, 2].map(|axis| (second_direction[axis] - first_direction[axis]) / c);
+        let d_t_e = dot(&difference, &velocity);
+        let slowness = 1.0 + dot(&first_direction, &velocity) / c;
+        let mut gradient = vec![0.0; self.dimension()];
+        let mut moved = |column: usize, d_source: [f64; 3]| {
+            let t_e_shift = -dot(&first_direction, &d_source) / c / slowness;
+            gradient[column] = dot(&difference, &d_source) + d_t_e * t_e_shift;
+        };
+        moved(1, [1.0, 0.0, 0.0]);
+        moved(2, [0.0, 1.0, 0.0]);
+        if self.fits_altitude() {
+            moved(3, [0.0, 0.0, 1.0]);
+        }
+        moved(self.heading_index(), [v * t_e * cos, -v * t_e * sin, 0.0]);
+        moved(self.speed_index(), [t_e * sin, t_e * cos, 0.0]);
+        if let Some(index) = self.parameter_names().iter().position(|name| *name == "c") {
+            // t_e + r1 / c = t gives dt_e/dc = r1 / c^2 / (1 + u1 . V / c)
+            gradient[index] = -delay / c + d_t_e * first_range / (c * c) / slowness;
+        }
+        (delay, gradient)
+    }
+
+    /// Priors of the fitted speed of sound and wind, as in `DopplerModel::priors`.
+    pub fn priors(&self, params: &[f64]) -> Vec<(usize, f64, f64)> {
+        // the nuisance parameters sit at the end of both vectors
+        let mut device = vec![0.0; DEVICE_PARAMETERS];
+        device.extend(&params[self.geometry_dimension()..]);
+        let shift = self.geometry_dimension() - DEVICE_PARAMETERS;
+        self.doppler
+            .priors(&device)
+            .into_iter()
+            .map(|(index, residual, derivative)| (index + shift, residual, derivative))
+            .collect()
+    }
+
+    /// Brings equivalent solutions to one form: flying backwards is flying the other way, the
+    /// heading is kept in [0, 2π), and if all microphones are level, below them sounds the same
+    /// as above.
+    pub fn canonical(&self, params: &[f64], microphones: &[[f64; 3]]) -> Vec<f64> {
+        let mut params = params.to_vec();
+        let (heading, speed) = (self.heading_index(), self.speed_index());
+        if params[speed] < 0.0 {
+            let mut device = vec![0.0, 0.0, params[speed], 0.0];
+            device.extend(&params[self.geometry_dimension()..]);
+            let reversed = self.doppler.canonical(&device);
+            // only if the wind allows it
+            if reversed[2] > 0.0 {
+                params[speed] = reversed[2];
+                params[heading] += PI;
+                params[self.geometry_dimension()..].copy_from_slice(&reversed[DEVICE_PARAMETERS..]);
+            }
+        }
+        params[heading] = params[heading].rem_euclid(TAU);
+        if self.fits_altitude() {
+            let level = microphones[0][2];
+            if microphones.iter().all(|microphone| microphone[2] == level) {
+                params[3] = level + (params[3] - level).abs();
+            }
+        }
+        params
+    }
+}
+
+/// The shared track fitted to several recordings.
+pub struct MultiDeviceSolution {
+    model: ArrayModel,
+    // in the order of `model.parameter_names()`
+    params: Vec<f64>,
+    recordings: Vec<Recording>,
+    delays: Vec<Delay>,
+    report: FitReport,
+}
+
+impl MultiDeviceSolution {
+    pub fn model(&self) -> ArrayModel {
+        self.model
+    }
+
+    /// Fitted parameters, named by `model().parameter_names()`.
+    pub fn params(&self) -> &[f64] {
+        &self.params
+    }
+
+    pub fn report(&self) -> &FitReport {
+        &self.report
+    }
+
+    /// Rest frequency of the source.
+    pub fn source_frequency(&self) -> f64 {
+        self.params[0]
+    }
+
+    /// Direction of flight, degrees clockwise from north.
+    pub fn heading(&self) -> f64 {
+        self.params[self.model.heading_index()].to_degrees()
+    }
+
+    /// Ground speed (m/s).
+    pub fn speed(&self) -> f64 {
+        self.params[self.model.speed_index()]
+    }
+
+    // the speed of sound and wind are the same for every microphone
+    fn first_device_params(&self) -> Vec<f64> {
+        self.model.device_params(&self.params, &self.recordings[0].microphone)
+    }
+
+    /// Speed of sound (m/s), fitted or as configured.
+    pub fn speed_of_sound(&self) -> f64 {
+        self.model.doppler.speed_of_sound(&self.first_device_params())
+    }
+
+    /// Wind along the track (m/s), positive in the direction of flight.
+    pub fn wind(&self) -> f64 {
+        self.model.doppler.wind(&self.first_device_params())
+    }
+
+    /// Speed of the source through the air (m/s).
+    pub fn airspeed(&self) -> f64 {
+        self.model.doppler.airspeed(&self.first_device_params())
+    }
+
+    /// Position of the source at time t, m east, north and up of the origin.
+    pub fn position(&self, t: f64) -> [f64; 3] {
+        self.model.position(&self.params, t)
+    }
+
+    pub fn recordings(&self) -> &[Recording] {
+        &self.recordings
+    }
+
+    /// Delays between the recordings fitted along with their tracks.
+    pub fn delays(&self) -> &[Delay] {
+        &self.delays
+    }
+
+    /// Delay of the recording `second` behind `first` the fitted track predicts at time t, s.
+    pub fn predicted_delay(&self, first: usize, second: usize, t: f64) -> f64 {
+        let (first, second) = (&self.recordings[first].microphone, &self.recordings[second].microphone);
+        self.model.delay(&self.params, first, second, t).0
+    }
+
+    /// Distance (m) of `point` from the fitted track.
+    pub fn distance_from_track(&self, point: &[f64; 3]) -> f64 {
+        self.model.device_geometry(&self.params, point).distance
+    }
+
+    /// What the recording at `index` hears of the shared track, as a single-microphone
+    /// solution with the straight model; its report holds the residuals of that recording.
+    pub fn device(&self, index: usize) -> OneDeviceSolution {
+        let recording = &self.recordings[index];
+        let params = self.model.device_params(&self.params, &recording.microphone);
+        let (objective, rms) = device_residuals(self.model.doppler, &recording.observations, &params);
+        OneDeviceSolution {
+            model: self.model.doppler,
+            params,
+            tau0: recording.observations.tau0,
+            channel: recording.channel,
+            report: FitReport {
+                objective,
+                rms,
+                history: vec![],
+                ..self.report.clone()
+            },
+            uncertainty: None,
+            bootstrap: None,
+        }
+    }
+}
+
+fn device_residuals(model: DopplerModel, observations: &Observations, params: &[f64]) -> (f64, crate::spectrum::Hz) {
+    let objective = observations
+        .points
+        .iter()
+        .map(|y| ((model.frequency(params, observations.tau0, y.time) - y.frequency.0) * weight(y)).powi(2))
+        .sum();
+    (objective, residual_rms(model, observations, params))
+}
+
+struct MultiDeviceProblem<'a> {
+    model: ArrayModel,
+    recordings: &'a [Recording],
+    delays: &'a [Delay],
+    p: DVector<f64>,
+    history: RefCell<Vec<Iterate>>,
+}
+
+impl MultiDeviceProblem<'_> {
+    fn microphones(&self, delay: &Delay) -> (&[f64; 3], &[f64; 3]) {
+        (&self.recordings[delay.first].microphone, &self.recordings[delay.second].microphone)
+    }
+
+    fn rows(&self) -> usize {
+        self.recordings.iter().map(|r| r.observations.points.len()).sum::<usize>()
+            + self.delays.len()
+            + self.model.priors(self.p.as_slice()).len()
+    }
+}
+
+impl LeastSquaresProblem<f64, Dyn, Dyn> for MultiDeviceProblem<'_> {
+    type ResidualStorage = Owned<f64, Dyn>;
+    type JacobianStorage = Owned<f64, Dyn, Dyn>;
+    type ParameterStorage = Owned<f64, Dyn>;
+
+    fn set_params(&mut self, x: &DVector<f64>) {
+        self.p.copy_from(x);
+        let nuisance = self.model.geometry_dimension() - DEVICE_PARAMETERS;
+        self.model.doppler.clamp_nuisance(&mut self.p.as_mut_slice()[nuisance..]);
+    }
+
+    fn params(&self) -> DVector<f64> {
+        self.p.clone()
+    }
+
+    // the recordings one after the other, then the delays between them, then the priors
+    fn residuals(&self) -> Option<DVector<f64>> {
+        let params = self.p.as_slice();
+        let mut residuals = Vec::with_capacity(self.rows());
+        for recording in self.recordings {
+            let device = self.model.device_params(params, &recording.microphone);
+            let tau0 = recording.observations.tau0;
+            for y in &recording.observations.points {
+                let nu = self.model.doppler.frequency(&device, tau0, y.time);
+                residuals.push((nu - y.frequency.0) * weight(y));
+            }
+        }
+        for delay in self.delays {
+            let (first, second) = self.microphones(delay);
+            let (expected, _) = self.model.delay(params, first, second, delay.t);
+            residuals.push((expected - delay.delay) / delay.uncertainty);
+        }
+        residuals.extend(self.model.priors(params).into_iter().map(|(_, residual, _)| residual));
+        let residuals = DVector::from_vec(residuals);
+
+        let mut history = self.history.borrow_mut();
+        let iteration = history.len();
+        history.push(Iterate {
+            iteration,
+            params: params.to_vec(),
+            objective: residuals.norm_squared(),
+        });
+        Some(residuals)
+    }
+
+    fn jacobian(&self) -> Option<DMatrix<f64>> {
+        let params = self.p.as_slice();
+        let mut jacobian = DMatrix::<f64>::zeros(self.rows(), self.p.len());
+        let mut row = 0;
+        for recording in self.recordings {
+            let tau0 = recording.observations.tau0;
+            for y in &recording.observations.points {
+                let (_, gradient) = self.model.gradient(params, &recording.microphone, tau0, y.time);
+                for (column, value) in gradient.iter().enumerate() {
+                    jacobian[(row, column)] = value * weight(y);
+                }
+                row += 1;
+            }
+        }
+        for delay in self.delays {
+            let (first, second) = self.microphones(delay);
+            let (_, gradient) = self.model.delay(params, first, second, delay.t);
+            for (column, value) in gradient.iter().enumerate() {
+                jacobian[(row, column)] = value / delay.uncertainty;
+            }
+            row += 1;
+        }
+        for (column, _, derivative) in self.model.priors(params) {
+            jacobian[(row, column)] = derivative;
+            row += 1;
+        }
+        Some(jacobian)
+    }
+}
+
+// headings tried as starting points, and the two sides of the first microphone the track may pass
+const START_HEADINGS: usize = 12;
+
+/// Fits one track shared by all `recordings` with Levenberg–Marquardt, to their frequency tracks
+/// and to the `delays` measured between them, if any.
+///
+/// Every recording is first fitted on its own. The starting points put the track at the
+/// distance and time of closest approach the first recording found, on either side of its
+/// microphone, for headings every 30 degrees; the fit with the lowest objective is kept.
+/// `None` without recordings.
+pub fn multi_device_approximation(
+    recordings: Vec<Recording>,
+    delays: Vec<Delay>,
+    model: ArrayModel,
+    stopping: &StoppingCriteria,
+) -> Option<MultiDeviceSolution> {
+    let first = recordings.first()?;
+    let solo = LevenbergMarquardtSolver { stopping: *stopping };
+    let singles: Vec<OneDeviceSolution> = recordings
+        .iter()
+        .map(|recording| {
+            one_device_approximation(
+                recording.observations.points.clone(),
+                recording.observations.tau0,
+                recording.channel,
+                model.doppler,
+                &solo,
+                None,
+            )
+        })
+        .collect();
+
+    let mut frequencies: Vec<f64> = singles.iter().filter_map(|single| single.source_frequency()).map(|f| f.0).collect();
+    frequencies.sort_by(f64::total_cmp);
+    let f0 = frequencies[frequencies.len() / 2];
+    let speed = singles.iter().map(|single| single.speed()).sum::<f64>() / singles.len() as f64;
+    let (distance, t_closest) = (singles[0].distance(), singles[0].closest_approach());
+    let nuisance = singles[0].params()[DEVICE_PARAMETERS..].to_vec();
+    let microphone = first.microphone;
+    let up = match model.altitude {
+        Altitude::Known(up) => up,
+        Altitude::Fitted => microphone[2] + distance / 2.0,
+    };
+    let horizontal = other_component(distance, up - microphone[2]);
+
+    let microphones: Vec<[f64; 3]> = recordings.iter().map(|recording| recording.microphone).collect();
+    let mut best: Option<(Vec<f64>, FitReport)> = None;
+    for step in 0..START_HEADINGS {
+        let heading = TAU * step as f64 / START_HEADINGS as f64;
+        let (sin, cos) = heading.sin_cos();
+        for side in [1.0, -1.0] {
+            // at t = 0 the source is speed * t_closest short of its closest approach, which is
+            // `horizontal` to the side of the microphone
+            let east = microphone[0] - speed * t_closest * sin - side * horizontal * cos;
+            let north = microphone[1] - speed * t_closest * cos + side * horizontal * sin;
+            let mut initial = vec![f0, east, north];
+            if model.fits_altitude() {
+                initial.push(up);
+            }
+            initial.extend([heading, speed]);
+            initial.extend(&nuisance);
+
+            let (params, report) = fit(model, &recordings, &delays, &microphones, &initial, stopping);
+            if best.as_ref().is_none_or(|(_, best)| report.objective < best.objective) {
+                best = Some((params, report));
+            }
+        }
+    }
+    let (params, report) = best?;
+    Some(MultiDeviceSolution {
+        model,
+        params,
+        recordings,
+        delays,
+        report,
+    })
+}
+
+fn fit(
+    model: ArrayModel,
+    recordings: &[Recording],
+    delays: &[Delay],
+    microphones: &[[f64; 3]],
+    initial: &[f64],
+    stopping: &StoppingCriteria,
+) -> (Vec<f64>, FitReport) {
+    let problem = MultiDeviceProblem {
+        model,
+        recordings,
+        delays,
+        p: DVector::from_column_slice(initial),
+        history: RefCell::new(vec![]),
+    };
+    let (result, report) = LevenbergMarquardt::new()
+        .with_patience(stopping.max_iterations.max(1))
+        .with_xtol(stopping.step_tolerance)
+        .with_ftol(stopping.step_tolerance)
+        .minimize(problem);
+
+    let params = model.canonical(result.p.as_slice(), microphones);
+    let (mut squares, mut count) = (0.0, 0);
+    for recording in recordings {
+        let device = model.device_params(&params, &recording.microphone);
+        let rms = residual_rms(model.doppler, &recording.observations, &device).0;
+        squares += rms * rms * recording.observations.points.len() as f64;
+        count += recording.observations.points.len();
+    }
+    (
+        params,
+        FitReport {
+            solver: "Levenberg-Marquardt",
+            termination: termination(report.termination),
+            objective: 2.0 * report.objective_function,
+            rms: crate::spectrum::Hz((squares / count.max(1) as f64).sqrt()),
+            iterations: report.number_of_evaluations,
+            history: result.history.into_inner(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sound::{SpeedOfSound, Wind};
+
+    #[test]
+    fn delay_gradient_matches_finite_differences() {
+        let (first, second) = ([30.0, -20.0, 2.0], [-40.0, 50.0, 0.0]);
+        for altitude in [Altitude::Fitted, Altitude::Known(80.0)] {
+            for retarded_time in [false, true] {
+                let doppler = DopplerModel {
+                    retarded_time,
+                    ..DopplerModel::new(Formula::Classical, SpeedOfSound::FITTED, Wind::FITTED)
+                };
+                let model = ArrayModel::new(doppler, altitude);
+                // f0, east, north, [up], heading, v, c, w
+                let mut params = vec![350.0, -200.0, 120.0];
+                if altitude == Altitude::Fitted {
+                    params.push(90.0);
+                }
+                params.extend([0.7, 60.0, 330.0, 6.0]);
+                assert_eq!(params.len(), model.dimension());
+
+                for t in [0.0, 2.0, 5.0, 9.0] {
+                    let (_, gradient) = model.delay(&params, &first, &second, t);
+                    for (i, analytic) in gradient.iter().enumerate() {
+                        let h = 1e-6 * params[i].abs().max(1.0);
+                        let (mut above, mut below) = (params.clone(), params.clone());
+                        above[i] += h;
+                        below[i] -= h;
+                        let numeric = (model.delay(&above, &first, &second, t).0 - model.delay(&below, &first, &second, t).0) / (2.0 * h);
+                        assert!(
+                            (numeric - analytic).abs() <= 1e-5 * numeric.abs().max(1e-6),
+                            "{:?} at {} s, parameter {}: {} against {}",
+                            altitude,
+                            t,
+                            i,
+                            analytic,
+                            numeric
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,35 +2,36 @@ use std::io::Write;
 
 use plotters::{prelude::*, style::full_palette::ORANGE};
 
-use crate::{approx::C, OneDeviceSolution};
+use crate::tracking::{merge_simultaneous, TrackPoint};
+use crate::OneDeviceSolution;
 
+// heard frequency of the fitted model, at the emission time if the model corrects for it
 fn get_value(model: &OneDeviceSolution, t: f64) -> f64 {
-    let (x0, d, v0, tau0) = (model.x0, model.d, model.v0, model.tau0);
-    let alpha = x0 + v0 * t + v0 * tau0;
-    let beta = x0 + v0 * t;
-    #[allow(non_snake_case)]
-    let A = (d.powi(2) + alpha.powi(2)).sqrt();
-    #[allow(non_snake_case)]
-    let B = (d.powi(2) + beta.powi(2)).sqrt();
-    let nu = C / (tau0 * C + A - B);
-    nu
+    model.frequency(t).0
 }
 
-fn build_approximation_graph(model: OneDeviceSolution, len: usize) -> Vec<f64> {
+fn build_approximation_graph(model: OneDeviceSolution, track: &[TrackPoint]) -> Vec<f64> {
     let mut graph = vec![];
-    for i in 0..len {
-        graph.push(get_value(&model, i as f64 * model.tau0));
+    for point in track {
+        graph.push(get_value(&model, point.time));
     }
     graph
 }
 
 // https://github.com/plotters-rs/plotters/blob/master/plotters/examples/area-chart.rs
-const OUT_FILE_NAME: &str = "plotters-doc-data/frequency-chart-with-approx-7.png";
-pub fn plot(data: Vec<f64>, optional_model: Option<OneDeviceSolution>, caption: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let root = BitMapBackend::new(OUT_FILE_NAME, (1024, 768)).into_drawing_area();
+pub const OUT_FILE_NAME: &str = "plotters-doc-data/frequency-chart-with-approx-7.png";
+pub fn plot(track: &[TrackPoint], optional_model: Option<OneDeviceSolution>, caption: &str) -> Result<(), Box<dyn std::error::Error>> {
+    plot_to(OUT_FILE_NAME, track, optional_model, caption)
+}
+
+pub fn plot_to(out_file_name: &str, track: &[TrackPoint], optional_model: Option<OneDeviceSolution>, caption: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::new(out_file_name, (1024, 768)).into_drawing_area();
 
     root.fill(&WHITE)?;
 
+    // one value per frame, however many harmonics were fitted
+    let track = &merge_simultaneous(track)[..];
+    let data: Vec<f64> = track.iter().map(|point| point.frequency.0).collect();
     let mut max_y: f64 = 0.0;
     for val in &data {
         max_y = max_y.max(*val);
@@ -46,6 +47,7 @@ pub fn plot(data: Vec<f64>, optional_model: Option<OneDeviceSolution>, caption:
         .configure_mesh()
         .disable_x_mesh()
         .disable_y_mesh()
+        .y_desc("Hz")
         .draw()?;
 
     chart.draw_series(
@@ -59,7 +61,7 @@ pub fn plot(data: Vec<f64>, optional_model: Option<OneDeviceSolution>, caption:
 
     if let Some(model) = optional_model {
         println!("Found model solution");
-        let model_data = build_approximation_graph(model, data.len());
+        let model_data = build_approximation_graph(model, track);
         chart.draw_series(
             AreaSeries::new(
                 (0..).zip(model_data.iter()).map(|(x, y)| (x, *y)),
@@ -72,7 +74,7 @@ pub fn plot(data: Vec<f64>, optional_model: Option<OneDeviceSolution>, caption:
 
     // To avoid the IO failure being ignored silently, we manually call the present function
     root.present().expect("Unable to write result to file, please make sure 'plotters-doc-data' dir exists under current dir");
-    println!("Result has been saved to {}", OUT_FILE_NAME);
+    println!("Result has been saved to {}", out_file_name);
 
     // let aboba_test = 
 
@@ -91,8 +93,7 @@ pub fn gif_plots(data: Vec<Vec<f64>>) -> Result<(), Box<dyn std::error::Error>>
         }
     }
 
-    let mut iter_counter: usize = 0;
-    for data_segment in data {
+    for (iter_counter, data_segment) in data.into_iter().enumerate() {
         root.fill(&WHITE)?;
 
         let mut chart = ChartBuilder::on(&root)
@@ -122,7 +123,6 @@ pub fn gif_plots(data: Vec<Vec<f64>>) -> Result<(), Box<dyn std::error::Error>>
             iter_counter,
         );
         std::io::stdout().flush().unwrap();
-        iter_counter += 1;
     }
 
     println!("Result has been saved to {}", OUT_FILE_NAME_GIF);
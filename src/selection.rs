@@ -0,0 +1,93 @@
+use crate::OneDeviceSolution;
+
+/// Information criterion weighing how well a model fits against how many parameters it takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Criterion {
+    /// Akaike: 2 per parameter.
+    Aic,
+    /// Bayesian: ln(n) per parameter, stricter on long tracks.
+    #[default]
+    Bic,
+}
+
+impl Criterion {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Criterion::Aic => "AIC",
+            Criterion::Bic => "BIC",
+        }
+    }
+
+    /// Score of a fit with weighted sum of squared residuals `objective` over `points`
+    /// observations and `parameters` free parameters; lower is better.
+    ///
+    /// The frequency uncertainties of the tracker are only relative, so the likelihood is that
+    /// of Gaussian residuals with their scale profiled out, -2 ln L = n ln(objective / n).
+    pub fn score(&self, objective: f64, points: usize, parameters: usize) -> f64 {
+        let n = points.max(1) as f64;
+        let penalty = match self {
+            Criterion::Aic => 2.0,
+            Criterion::Bic => n.ln(),
+        };
+        n * (objective / n).ln() + penalty * parameters as f64
+    }
+}
+
+/// Index of the solution `criterion` prefers among fits of different models to the same
+/// `points` observations; `None` if there are none.
+pub fn select(solutions: &[OneDeviceSolution], points: usize, criterion: Criterion) -> Option<usize> {
+    let scores = solutions
+        .iter()
+        .map(|solution| criterion.score(solution.report().objective, points, solution.model().dimension()));
+    scores
+        .enumerate()
+        .filter(|(_, score)| !score.is_nan())
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx::one_device_approximation;
+    use crate::loader::AudioChannel;
+    use crate::model::DopplerModel;
+    use crate::solver::LevenbergMarquardtSolver;
+    use crate::spectrum::Hz;
+    use crate::tracking::TrackPoint;
+    use crate::trajectory::Trajectory;
+
+    #[test]
+    fn a_straight_pass_is_straight() {
+        // a straight pass at 50 m and 40 m/s of a 300 Hz source, closest at 5 s, with ±0.5 Hz of
+        // error that no model explains
+        let truth = DopplerModel::default();
+        let mut state = 11u64;
+        let points: Vec<TrackPoint> = (0..200)
+            .map(|k| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let noise = (state >> 11) as f64 / (1u64 << 53) as f64 - 0.5;
+                let time = 0.05 * k as f64;
+                TrackPoint {
+                    time,
+                    frequency: Hz(truth.frequency(&[300.0, 50.0, 40.0, 5.0], 0.05, time) + noise),
+                    confidence: 1.0,
+                    uncertainty: Hz(1.0),
+                }
+            })
+            .collect();
+        let solutions: Vec<OneDeviceSolution> = Trajectory::ALL
+            .iter()
+            .map(|&trajectory| {
+                let model = DopplerModel { trajectory, ..truth };
+                one_device_approximation(points.clone(), 0.05, AudioChannel::Downmix, model, &LevenbergMarquardtSolver::default(), None)
+            })
+            .collect();
+        // the extra parameters can only fit the noise a little better
+        for solution in &solutions[1..] {
+            assert!(solution.report().objective <= solutions[0].report().objective * 1.001);
+        }
+        assert_eq!(select(&solutions, points.len(), Criterion::Aic), Some(0));
+        assert_eq!(select(&solutions, points.len(), Criterion::Bic), Some(0));
+    }
+}
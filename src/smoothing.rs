@@ -0,0 +1,193 @@
+use crate::spectrum::Spectrogram;
+
+/// How consecutive spectra are combined over time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Power mean over a centred sliding window; keeps every frame.
+    SlidingMean,
+    /// Exponential averaging of the power with the window as time constant, run forwards and
+    /// backwards so the track isn't delayed; keeps every frame.
+    Exponential,
+    /// Welch averaging: power mean over windows that overlap by the given fraction in [0, 1).
+    /// Decimates the spectrogram, dropping frames after the last whole window; with no overlap
+    /// this is a normalised version of summing groups of spectra.
+    Welch { overlap: f64 },
+    /// Median magnitude over a centred sliding window; keeps every frame.
+    Median,
+}
+
+/// Temporal aggregation settings.
+#[derive(Debug, Clone, Copy)]
+pub struct SmoothingConfig {
+    pub mode: Smoothing,
+    /// Window length (time constant for `Exponential`), seconds.
+    pub window: f64,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        SmoothingConfig {
+            mode: Smoothing::Welch { overlap: 0.5 },
+            window: 0.25,
+        }
+    }
+}
+
+/// Smooths the spectrogram over time in place.
+///
+/// Every output is a mean (or median) over the frames it actually covers, so windows cut short
+/// by the start or end of the recording aren't biased low. Times and the hop are updated to the
+/// new frames. The complex spectra are dropped, since averaged spectra have no phase.
+pub fn smooth(spectrogram: &mut Spectrogram, config: &SmoothingConfig) {
+    if spectrogram.is_empty() {
+        return;
+    }
+    let span = ((config.window / spectrogram.hop_duration).round() as usize).max(1);
+    let frames = spectrogram.len();
+    let bins = spectrogram.magnitudes[0].len();
+
+    match config.mode {
+        Smoothing::SlidingMean => {
+            let power = powers(spectrogram);
+            spectrogram.magnitudes = (0..frames)
+                .map(|frame| {
+                    let (lo, hi) = centred(frame, span, frames);
+                    mean_magnitude(&power[lo..hi], bins)
+                })
+                .collect();
+        }
+        Smoothing::Median => {
+            let magnitudes = &spectrogram.magnitudes;
+            spectrogram.magnitudes = (0..frames)
+                .map(|frame| {
+                    let (lo, hi) = centred(frame, span, frames);
+                    let mut column = vec![0.0; hi - lo];
+                    (0..bins)
+                        .map(|bin| {
+                            for (value, spectrum) in column.iter_mut().zip(&magnitudes[lo..hi]) {
+                                *value = spectrum[bin];
+                            }
+                            column.sort_by(f64::total_cmp);
+                            column[column.len() / 2]
+                        })
+                        .collect()
+                })
+                .collect();
+        }
+        Smoothing::Exponential => {
+            let alpha = 1.0 - (-spectrogram.hop_duration / config.window).exp();
+            let power = powers(spectrogram);
+            let run = |order: &mut dyn Iterator<Item = usize>| -> Vec<Vec<f64>> {
+                let mut result = vec![vec![]; frames];
+                let mut state: Option<Vec<f64>> = None;
+                for frame in order {
+                    let next = match state {
+                        None => power[frame].clone(),
+                        Some(previous) => previous
+                            .iter()
+                            .zip(&power[frame])
+                            .map(|(p, x)| p + alpha * (x - p))
+                            .collect(),
+                    };
+                    result[frame] = next.clone();
+                    state = Some(next);
+                }
+                result
+            };
+            let forward = run(&mut (0..frames));
+            let backward = run(&mut (0..frames).rev());
+            spectrogram.magnitudes = forward
+                .iter()
+                .zip(&backward)
+                .map(|(f, b)| f.iter().zip(b).map(|(f, b)| ((f + b) / 2.0).sqrt()).collect())
+                .collect();
+        }
+        Smoothing::Welch { overlap } => {
+            let step = ((span as f64 * (1.0 - overlap.clamp(0.0, 0.99))).round() as usize).max(1);
+            let power = powers(spectrogram);
+            let mut magnitudes = vec![];
+            let mut times = vec![];
+            // whole windows only, so the frames stay evenly spaced; a spectrogram shorter than
+            // one window becomes a single frame
+            for start in (0..=frames.saturating_sub(span)).step_by(step) {
+                let end = frames.min(start + span);
+                magnitudes.push(mean_magnitude(&power[start..end], bins));
+                times.push(spectrogram.times[start..end].iter().sum::<f64>() / (end - start) as f64);
+            }
+            spectrogram.magnitudes = magnitudes;
+            spectrogram.times = times;
+            spectrogram.hop_duration *= step as f64;
+        }
+    }
+    spectrogram.spectra.clear();
+}
+
+fn powers(spectrogram: &Spectrogram) -> Vec<Vec<f64>> {
+    spectrogram
+        .magnitudes
+        .iter()
+        .map(|spectrum| spectrum.iter().map(|m| m * m).collect())
+        .collect()
+}
+
+// RMS magnitude of a block of power spectra
+fn mean_magnitude(power: &[Vec<f64>], bins: usize) -> Vec<f64> {
+    let mut sum = vec![0.0; bins];
+    for spectrum in power {
+        for (total, value) in sum.iter_mut().zip(spectrum) {
+            *total += value;
+        }
+    }
+    sum.iter().map(|total| (total / power.len() as f64).sqrt()).collect()
+}
+
+// [lo, hi) of a window of `span` frames centred on `frame`, cut to the recording
+fn centred(frame: usize, span: usize, frames: usize) -> (usize, usize) {
+    let lo = frame.saturating_sub(span / 2);
+    let hi = (frame + span - span / 2).min(frames);
+    (lo, hi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a constant spectrum with one louder bin, at a 32 ms hop
+    fn steady(frames: usize) -> Spectrogram {
+        Spectrogram {
+            magnitudes: vec![(0..9).map(|bin| if bin == 4 { 2.0 } else { 0.5 }).collect(); frames],
+            spectra: vec![],
+            times: (0..frames).map(|frame| 0.016 + 0.032 * frame as f64).collect(),
+            hop_duration: 0.032,
+            sample_rate: 8000,
+            fft_len: 16,
+        }
+    }
+
+    #[test]
+    fn frames_stay_evenly_spaced() {
+        for mode in [Smoothing::SlidingMean, Smoothing::Exponential, Smoothing::Median, Smoothing::Welch { overlap: 0.5 }, Smoothing::Welch { overlap: 0.0 }] {
+            let mut spectrogram = steady(37);
+            smooth(&mut spectrogram, &SmoothingConfig { mode, window: 0.25 });
+            assert_eq!(spectrogram.times.len(), spectrogram.magnitudes.len(), "{:?}", mode);
+            assert!(spectrogram.len() > 1, "{:?}", mode);
+            for pair in spectrogram.times.windows(2) {
+                assert!((pair[1] - pair[0] - spectrogram.hop_duration).abs() < 1e-9, "{:?}: {:?}", mode, spectrogram.times);
+            }
+            // averaging a steady spectrum gives it back
+            for spectrum in &spectrogram.magnitudes {
+                assert!((spectrum[4] - 2.0).abs() < 1e-9 && (spectrum[0] - 0.5).abs() < 1e-9, "{:?}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn welch_drops_the_partial_window() {
+        // an 8 frame window stepping by 4 over 37 frames fits 8 whole windows
+        let mut spectrogram = steady(37);
+        smooth(&mut spectrogram, &SmoothingConfig { mode: Smoothing::Welch { overlap: 0.5 }, window: 0.25 });
+        assert_eq!(spectrogram.len(), 8);
+        assert!((spectrogram.hop_duration - 0.128).abs() < 1e-12);
+        assert!((spectrogram.times[0] - (0.016 + 0.032 * 3.5)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,365 @@
+use gomez::algo::NelderMead;
+use gomez::OptimizerDriver;
+
+use crate::approx::{find_return, residual_rms, GetResult, OneDeviceProblem, Progress};
+use crate::lma::get_solo_approximation;
+use crate::model::DopplerModel;
+use crate::tracking::TrackPoint;
+use crate::{FitReport, Iterate, Termination};
+
+/// Frequencies of one track, every point at its own time from the start of the recording.
+#[derive(Debug, Clone)]
+pub struct Observations {
+    pub points: Vec<TrackPoint>,
+    /// Time between frames, s; the legacy model is tied to it.
+    pub tau0: f64,
+}
+
+impl Observations {
+    /// Time of the last point, s.
+    pub fn end(&self) -> f64 {
+        self.points.last().map_or(0.0, |point| point.time)
+    }
+}
+
+/// When an iterative solver gives up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StoppingCriteria {
+    /// Upper bound on iterations (also on the grid search's zoom rounds).
+    pub max_iterations: usize,
+    /// Stop once the weighted sum of squared residuals falls below this.
+    pub objective_threshold: f64,
+    /// Stop once an accepted step moves the parameters less than this.
+    pub step_tolerance: f64,
+    /// A gomez solver that can't move any more has converged if every component of the
+    /// gradient, times its parameter, is below this share of the objective.
+    pub gradient_tolerance: f64,
+}
+
+impl Default for StoppingCriteria {
+    fn default() -> Self {
+        StoppingCriteria {
+            max_iterations: 100,
+            objective_threshold: 1e-6,
+            step_tolerance: 1e-8,
+            gradient_tolerance: 1e-4,
+        }
+    }
+}
+
+/// Fitted model parameters together with how the fit went.
+#[derive(Debug, Clone)]
+pub struct Fit {
+    pub params: Vec<f64>,
+    pub report: FitReport,
+}
+
+/// Anything that can fit a single-source Doppler model to a track.
+pub trait DopplerSolver {
+    fn name(&self) -> &'static str;
+
+    /// Fits the parameters of `model` starting from `initial`, handing every iterate to
+    /// `progress`.
+    fn solve(&self, model: DopplerModel, observations: &Observations, initial: &[f64], progress: Option<Progress>) -> Fit;
+
+    /// Whether the result depends on `initial`, i.e. whether restarting from other points helps.
+    fn uses_initial_guess(&self) -> bool {
+        true
+    }
+}
+
+/// Solvers selectable from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SolverBackend {
+    /// gomez trust-region on the scalar objective.
+    #[default]
+    TrustRegion,
+    /// gomez Nelder–Mead simplex; derivative free.
+    NelderMead,
+    /// Levenberg–Marquardt with the analytic Jacobian.
+    LevenbergMarquardt,
+    /// Exhaustive grid that is refined around its best cell.
+    GridSearch,
+}
+
+impl SolverBackend {
+    pub const ALL: [SolverBackend; 4] = [
+        SolverBackend::TrustRegion,
+        SolverBackend::NelderMead,
+        SolverBackend::LevenbergMarquardt,
+        SolverBackend::GridSearch,
+    ];
+
+    pub fn solver(self, stopping: StoppingCriteria) -> Box<dyn DopplerSolver> {
+        match self {
+            SolverBackend::TrustRegion => Box::new(TrustRegionSolver { stopping }),
+            SolverBackend::NelderMead => Box::new(NelderMeadSolver { stopping }),
+            SolverBackend::LevenbergMarquardt => Box::new(LevenbergMarquardtSolver { stopping }),
+            SolverBackend::GridSearch => Box::new(GridSearchSolver {
+                stopping,
+                ..GridSearchSolver::default()
+            }),
+        }
+    }
+}
+
+// report for the gomez drivers, which share `find_return`
+fn gomez_fit(solver: &'static str, model: DopplerModel, observations: &Observations, result: GetResult) -> Fit {
+    let mut params = result.best.params;
+    model.limit_speed(&mut params, &observations.points);
+    let params = model.canonical(&params);
+    let rms = residual_rms(model, observations, &params);
+    Fit {
+        params,
+        report: FitReport {
+            solver,
+            termination: result.termination,
+            objective: result.best.objective,
+            rms,
+            iterations: result.history.len() - 1,
+            history: result.history,
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrustRegionSolver {
+    pub stopping: StoppingCriteria,
+}
+
+impl DopplerSolver for TrustRegionSolver {
+    fn name(&self) -> &'static str {
+        "Trust-region"
+    }
+
+    fn solve(&self, model: DopplerModel, observations: &Observations, initial: &[f64], progress: Option<Progress>) -> Fit {
+        let problem = OneDeviceProblem::new(model, observations);
+        let mut optimizer = OptimizerDriver::builder(&problem)
+            .with_initial(initial.to_vec())
+            .build();
+        let result = find_return(&problem, &mut optimizer, &self.stopping, progress);
+        gomez_fit(self.name(), model, observations, result)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NelderMeadSolver {
+    pub stopping: StoppingCriteria,
+}
+
+impl DopplerSolver for NelderMeadSolver {
+    fn name(&self) -> &'static str {
+        "Nelder-Mead"
+    }
+
+    fn solve(&self, model: DopplerModel, observations: &Observations, initial: &[f64], progress: Option<Progress>) -> Fit {
+        let problem = OneDeviceProblem::new(model, observations);
+        let mut optimizer = OptimizerDriver::builder(&problem)
+            .with_algo(NelderMead::new)
+            .with_initial(initial.to_vec())
+            .build();
+        let result = find_return(&problem, &mut optimizer, &self.stopping, progress);
+        gomez_fit(self.name(), model, observations, result)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LevenbergMarquardtSolver {
+    pub stopping: StoppingCriteria,
+}
+
+impl DopplerSolver for LevenbergMarquardtSolver {
+    fn name(&self) -> &'static str {
+        "Levenberg-Marquardt"
+    }
+
+    fn solve(&self, model: DopplerModel, observations: &Observations, initial: &[f64], progress: Option<Progress>) -> Fit {
+        let (params, report) = get_solo_approximation(
+            model,
+            observations.points.clone(),
+            observations.tau0,
+            initial,
+            &self.stopping,
+            progress,
+        );
+        Fit { params, report }
+    }
+}
+
+/// Evaluates the objective on a regular grid over the plausible ranges of the model, shrinks
+/// the grid around the best point for a few rounds, and hands that point to Levenberg–Marquardt
+/// to finish. Slow, but cannot be trapped by a bad initial guess inside its ranges.
+#[derive(Debug, Clone, Copy)]
+pub struct GridSearchSolver {
+    pub stopping: StoppingCriteria,
+    /// Objective evaluations per round, spread evenly over the parameter axes.
+    pub points: usize,
+    /// Upper bound on zoom rounds before the local solver takes over.
+    pub rounds: usize,
+}
+
+impl Default for GridSearchSolver {
+    fn default() -> Self {
+        GridSearchSolver {
+            stopping: StoppingCriteria::default(),
+            points: 9261,
+            rounds: 4,
+        }
+    }
+}
+
+// every zoom round keeps this many cells around the best point on each side, but at most half
+// of the range, which coarse grids over many parameters would otherwise barely shrink
+const GRID_ZOOM_CELLS: f64 = 2.0;
+
+impl DopplerSolver for GridSearchSolver {
+    fn name(&self) -> &'static str {
+        "Grid search"
+    }
+
+    fn uses_initial_guess(&self) -> bool {
+        false
+    }
+
+    // the grid covers the model's search ranges, so the initial guess is only a fallback
+    fn solve(&self, model: DopplerModel, observations: &Observations, initial: &[f64], mut progress: Option<Progress>) -> Fit {
+        let problem = OneDeviceProblem::new(model, observations);
+        let bounds = model.search_ranges(observations);
+        let dimension = bounds.len();
+        let steps = ((self.points as f64).powf(1.0 / dimension as f64).round() as usize).max(2);
+        let mut ranges = bounds.clone();
+        // one iterate per round: the best grid point so far
+        let mut best = Iterate {
+            iteration: 0,
+            objective: problem.objective(initial),
+            params: initial.to_vec(),
+        };
+        let mut history = vec![best.clone()];
+
+        let mut params = vec![0.0; dimension];
+        let termination = loop {
+            let spacing: Vec<f64> = ranges.iter().map(|(lo, hi)| (hi - lo) / (steps - 1) as f64).collect();
+            for index in 0..steps.pow(dimension as u32) {
+                // mixed-radix digits of the index are the grid coordinates
+                let mut rest = index;
+                for axis in 0..dimension {
+                    params[axis] = ranges[axis].0 + (rest % steps) as f64 * spacing[axis];
+                    rest /= steps;
+                }
+                let objective = problem.objective(&params);
+                if objective < best.objective {
+                    best.params.copy_from_slice(&params);
+                    best.objective = objective;
+                }
+            }
+            best.iteration = history.len();
+            if let Some(progress) = progress.as_deref_mut() {
+                progress(&best);
+            }
+            let previous = history.last().map_or(f64::INFINITY, |iterate| iterate.objective);
+            history.push(best.clone());
+
+            if best.objective <= self.stopping.objective_threshold {
+                break Termination::ObjectiveBelowThreshold;
+            }
+            if spacing.iter().all(|s| *s < self.stopping.step_tolerance) {
+                break Termination::StepBelowTolerance;
+            }
+            // a round that hardly lowers the objective has found the basin; the local solver
+            // gets there far sooner than further rounds
+            if previous - best.objective <= self.stopping.step_tolerance * previous {
+                break Termination::StepBelowTolerance;
+            }
+            if best.iteration >= self.rounds.min(self.stopping.max_iterations) {
+                break Termination::IterationLimit;
+            }
+            // zoom in, but never outside of the search ranges
+            for (axis, range) in ranges.iter_mut().enumerate() {
+                let half = (GRID_ZOOM_CELLS * spacing[axis]).min((range.1 - range.0) / 4.0);
+                let (lo, hi) = bounds[axis];
+                *range = ((best.params[axis] - half).max(lo), (best.params[axis] + half).min(hi));
+            }
+        };
+
+        let rounds = history.len() - 1;
+        // number the local solver's iterates on from the rounds
+        let mut polishing = |iterate: &Iterate| {
+            if let Some(progress) = progress.as_deref_mut() {
+                progress(&Iterate {
+                    iteration: rounds + iterate.iteration,
+                    ..iterate.clone()
+                });
+            }
+        };
+        let (polished, report) = get_solo_approximation(
+            model,
+            observations.points.clone(),
+            observations.tau0,
+            &best.params,
+            &self.stopping,
+            Some(&mut polishing),
+        );
+        // keep the grid point if the local solver only made it worse
+        let (params, termination, objective) = if report.objective <= best.objective {
+            history.extend(report.history.into_iter().skip(1).map(|iterate| Iterate {
+                iteration: rounds + iterate.iteration,
+                ..iterate
+            }));
+            (polished, report.termination, report.objective)
+        } else {
+            model.limit_speed(&mut best.params, &observations.points);
+            (model.canonical(&best.params), termination, best.objective)
+        };
+        Fit {
+            report: FitReport {
+                solver: self.name(),
+                termination,
+                objective,
+                rms: residual_rms(model, observations, &params),
+                iterations: history.len() - 1,
+                history,
+            },
+            params,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hz;
+
+    // a straight pass at 50 m and 40 m/s of a 300 Hz source, closest at 5 s, heard without noise
+    const TRUTH: [f64; 4] = [300.0, 50.0, 40.0, 5.0];
+
+    fn pass(model: DopplerModel) -> Observations {
+        let points = (0..100)
+            .map(|k| {
+                let time = 0.1 * k as f64;
+                TrackPoint {
+                    time,
+                    frequency: Hz(model.frequency(&TRUTH, 0.1, time)),
+                    confidence: 1.0,
+                    uncertainty: Hz(1.0),
+                }
+            })
+            .collect();
+        Observations { points, tau0: 0.1 }
+    }
+
+    #[test]
+    fn every_backend_recovers_a_clean_pass() {
+        let model = DopplerModel::default();
+        let observations = pass(model);
+        // Nelder-Mead needs a few hundred iterations, the others a handful; the guess is about as
+        // far off as the one from the track shape
+        for backend in SolverBackend::ALL {
+            let solver = backend.solver(StoppingCriteria { max_iterations: 2000, ..StoppingCriteria::default() });
+            let fit = solver.solve(model, &observations, &[305.0, 45.0, 42.0, 5.2], None);
+            for (fitted, truth) in fit.params.iter().zip(TRUTH) {
+                assert!((fitted - truth).abs() < 1e-3 * truth, "{}: {:?}", solver.name(), fit.params);
+            }
+            assert!(fit.report.rms.0 < 1e-3, "{}: {}", solver.name(), fit.report.rms.0);
+        }
+    }
+}
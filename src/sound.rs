@@ -0,0 +1,154 @@
+use crate::approx::C;
+
+/// Conditions of the air the sound travelled through.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Air {
+    /// Degrees Celsius.
+    pub temperature: f64,
+    /// Relative humidity in percent.
+    pub relative_humidity: f64,
+    /// Metres above sea level of the site; only enters through the pressure in the humidity
+    /// term.
+    pub elevation: f64,
+}
+
+impl Default for Air {
+    fn default() -> Self {
+        Air {
+            temperature: 20.0,
+            relative_humidity: 0.0,
+            elevation: 0.0,
+        }
+    }
+}
+
+impl Air {
+    /// Speed of sound in m/s.
+    ///
+    /// Dry air follows 331.3 * sqrt(1 + T / 273.15). Water vapour is lighter than air, and its
+    /// mole fraction h (from the Magnus saturation pressure and the standard atmosphere
+    /// pressure at the elevation) raises that by about a factor 1 + 0.16 h, at most ~0.4 % at
+    /// 20 °C.
+    pub fn speed_of_sound(&self) -> f64 {
+        let dry = 331.3 * (1.0 + self.temperature / 273.15).sqrt();
+        // Magnus formula over water, Pa
+        let saturation = 610.94 * (17.625 * self.temperature / (self.temperature + 243.04)).exp();
+        let pressure = 101_325.0 * (1.0 - 2.255_77e-5 * self.elevation).powf(5.255_88);
+        let mole_fraction = (self.relative_humidity / 100.0).clamp(0.0, 1.0) * saturation / pressure;
+        dry * (1.0 + 0.16 * mole_fraction)
+    }
+}
+
+/// Where the speed of sound of the models comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpeedOfSound {
+    /// Known value in m/s, e.g. from `Air::speed_of_sound`.
+    Fixed(f64),
+    /// Fitted together with the pass, confined to `min..=max` m/s.
+    Fitted { min: f64, max: f64 },
+}
+
+impl Default for SpeedOfSound {
+    fn default() -> Self {
+        SpeedOfSound::Fixed(C)
+    }
+}
+
+impl SpeedOfSound {
+    /// Bounds wide enough for any weather a recording is made in, -40 to +50 °C.
+    pub const FITTED: SpeedOfSound = SpeedOfSound::Fitted { min: 306.0, max: 361.0 };
+
+    pub fn is_fitted(&self) -> bool {
+        matches!(self, SpeedOfSound::Fitted { .. })
+    }
+
+    /// The value to assume before fitting.
+    pub fn nominal(&self) -> f64 {
+        match *self {
+            SpeedOfSound::Fixed(c) => c,
+            SpeedOfSound::Fitted { min, max } => C.clamp(min, max),
+        }
+    }
+
+    /// Lowest value the model may take, used to keep the source subsonic.
+    pub fn min(&self) -> f64 {
+        match *self {
+            SpeedOfSound::Fixed(c) => c,
+            SpeedOfSound::Fitted { min, .. } => min,
+        }
+    }
+}
+
+/// Steady wind along the track axis in m/s, positive when it blows the way the source flies.
+///
+/// It carries the sound, so along the line of sight the sound travels at c plus the wind
+/// component; the source's airspeed is its ground speed minus the wind.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wind {
+    /// Known value, e.g. from a weather report.
+    Fixed(f64),
+    /// Fitted together with the pass, confined to `min..=max` m/s.
+    Fitted { min: f64, max: f64 },
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Wind::Fixed(0.0)
+    }
+}
+
+impl Wind {
+    /// Up to a strong breeze either way.
+    pub const FITTED: Wind = Wind::Fitted { min: -15.0, max: 15.0 };
+
+    pub fn is_fitted(&self) -> bool {
+        matches!(self, Wind::Fitted { .. })
+    }
+
+    /// The value to assume before fitting: calm, if the bounds allow it.
+    pub fn nominal(&self) -> f64 {
+        match *self {
+            Wind::Fixed(w) => w,
+            Wind::Fitted { min, max } => 0.0f64.clamp(min, max),
+        }
+    }
+
+    /// Lowest value the model may take, used to keep the source subsonic.
+    pub fn min(&self) -> f64 {
+        match *self {
+            Wind::Fixed(w) => w,
+            Wind::Fitted { min, .. } => min,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_of_sound_in_dry_air() {
+        assert!((Air::default().speed_of_sound() - 343.2).abs() < 0.1);
+        let freezing = Air {
+            temperature: 0.0,
+            ..Air::default()
+        };
+        assert!((freezing.speed_of_sound() - 331.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn humidity_speeds_sound_up_a_little_more_up_high() {
+        let dry = Air::default().speed_of_sound();
+        let humid = Air {
+            relative_humidity: 100.0,
+            ..Air::default()
+        };
+        let raise = humid.speed_of_sound() / dry - 1.0;
+        assert!(raise > 0.002 && raise < 0.004, "{}", raise);
+        let high = Air {
+            elevation: 2000.0,
+            ..humid
+        };
+        assert!(high.speed_of_sound() > humid.speed_of_sound());
+    }
+}
@@ -0,0 +1,435 @@
+use std::sync::Arc;
+
+use gomez::nalgebra as na;
+use gomez::{Domain, Function, OptimizerDriver, Problem};
+use na::{Dyn, IsContiguous};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use crate::multi::Altitude;
+use crate::spectrum::Window;
+
+/// Time difference of arrival between two recordings, measured in one frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Delay {
+    /// Centre of the frame of `first`, s from the start of the recordings: the clock of
+    /// `TrackPoint::time`, so delays and frequencies of one fit are evaluated at the same times.
+    pub t: f64,
+    /// Indices of the two recordings.
+    pub first: usize,
+    pub second: usize,
+    /// How much later the sound reaches `second` than `first`, s.
+    pub delay: f64,
+    /// Rough standard deviation of `delay`, s: the width of the GCC-PHAT peak over the
+    /// coherence, and what the uncertain microphone positions add.
+    pub uncertainty: f64,
+    /// Height of the GCC-PHAT peak in [0, 1]; 1 for a pure delay.
+    pub coherence: f64,
+}
+
+/// Framing of the signals for the delay estimates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TdoaConfig {
+    /// Frame length, s. Long frames average more, but the delay must not change much within.
+    /// Without a guide, pairs of distant microphones get longer frames, see `frame_delays`.
+    pub frame_duration: f64,
+    /// Step between frames, s.
+    pub hop_duration: f64,
+    /// Frames with a lower GCC-PHAT peak are dropped.
+    pub min_coherence: f64,
+    /// How far the delay may be from the guide, s.
+    pub max_correction: f64,
+    /// How well the positions of the microphones are known, m. Each metre can move a delay by
+    /// 3 ms, far more than GCC-PHAT is off in a clean frame.
+    pub microphone_error: f64,
+}
+
+impl Default for TdoaConfig {
+    fn default() -> Self {
+        TdoaConfig {
+            frame_duration: 0.2,
+            hop_duration: 0.1,
+            min_coherence: 0.05,
+            max_correction: 0.03,
+            microphone_error: 1.0,
+        }
+    }
+}
+
+// slowest sound the lag search allows for, about -50 °C
+const MIN_SPEED_OF_SOUND: f64 = 300.0;
+
+// frames are at least this many times the longest delay of the pair, or the two frames share
+// too little of the sound to correlate
+const FRAMES_PER_DELAY: f64 = 4.0;
+
+/// Highest point of a cross-correlation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peak {
+    /// Lag of the second signal behind the first, samples.
+    pub lag: f64,
+    /// In [0, 1] for GCC-PHAT.
+    pub height: f64,
+    /// Standard deviation of a Gaussian with the curvature of the peak, samples.
+    pub width: f64,
+}
+
+/// Generalised cross-correlation with phase transform of frames `len` samples long, with the
+/// FFTs planned once for all of them.
+pub struct GccPhat {
+    len: usize,
+    size: usize,
+    window: Vec<f64>,
+    forward: Arc<dyn Fft<f64>>,
+    inverse: Arc<dyn Fft<f64>>,
+}
+
+impl GccPhat {
+    pub fn new(planner: &mut FftPlanner<f64>, len: usize) -> Self {
+        // zero padding keeps the circular correlation from wrapping around
+        let size = (2 * len).next_power_of_two();
+        GccPhat {
+            len,
+            size,
+            window: Window::Hann.coefficients(len),
+            forward: planner.plan_fft_forward(size),
+            inverse: planner.plan_fft_inverse(size),
+        }
+    }
+
+    fn spectrum(&self, signal: &[f64]) -> Vec<Complex<f64>> {
+        let mut buffer: Vec<Complex<f64>> = (0..self.size)
+            .map(|i| Complex {
+                re: if i < self.len { signal[i] * self.window[i] } else { 0.0 },
+                im: 0.0,
+            })
+            .collect();
+        self.forward.process(&mut buffer);
+        buffer
+    }
+
+    /// Peak of the correlation of two frames of `len` samples.
+    ///
+    /// The lag is refined by a parabola through the peak. Only lags up to `max_lag` are
+    /// searched; `None` if the peak lies on that edge, since the true one is then beyond it.
+    pub fn peak(&self, first: &[f64], second: &[f64], max_lag: usize) -> Option<Peak> {
+        let (n, size) = (self.len, self.size);
+        if n < 2 || first.len() < n || second.len() < n {
+            return None;
+        }
+        let (a, b) = (self.spectrum(first), self.spectrum(second));
+
+        // whitened cross-spectrum: only the phase, i.e. the delay, is left
+        let mut cross: Vec<Complex<f64>> = a
+            .iter()
+            .zip(&b)
+            .map(|(x, y)| {
+                let product = x.conj() * y;
+                let magnitude = product.norm();
+                if magnitude > f64::EPSILON {
+                    product / magnitude
+                } else {
+                    Complex { re: 0.0, im: 0.0 }
+                }
+            })
+            .collect();
+        self.inverse.process(&mut cross);
+
+        // negative lags wrap around to the end
+        let correlation = |lag: isize| cross[lag.rem_euclid(size as isize) as usize].re / size as f64;
+        let max_lag = max_lag.min(n - 1) as isize;
+        let peak = (-max_lag..=max_lag).max_by(|x, y| correlation(*x).total_cmp(&correlation(*y)))?;
+        if peak.abs() == max_lag {
+            return None;
+        }
+        let (left, centre, right) = (correlation(peak - 1), correlation(peak), correlation(peak + 1));
+        let curvature = left - 2.0 * centre + right;
+        if curvature >= 0.0 || centre <= 0.0 {
+            return None;
+        }
+        Some(Peak {
+            lag: peak as f64 + (0.5 * (left - right) / curvature).clamp(-0.5, 0.5),
+            height: centre,
+            // a Gaussian of height h and deviation w has curvature -h / w^2 at its top
+            width: (-centre / curvature).sqrt(),
+        })
+    }
+}
+
+/// Linear interpolation of `signal` from `from` to `to` samples per second.
+pub fn resample(signal: &[f64], from: u32, to: u32) -> Vec<f64> {
+    if from == to || signal.is_empty() {
+        return signal.to_vec();
+    }
+    let ratio = from as f64 / to as f64;
+    let len = ((signal.len() - 1) as f64 / ratio).floor() as usize + 1;
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let index = position.floor() as usize;
+            let fraction = position - index as f64;
+            let next = signal.get(index + 1).copied().unwrap_or(signal[index]);
+            signal[index] * (1.0 - fraction) + next * fraction
+        })
+        .collect()
+}
+
+/// Expected delay of the second recording behind the first at time t, s.
+pub type Guide<'a> = &'a dyn Fn(usize, usize, f64) -> f64;
+
+/// Delays between every pair of `signals`, frame by frame.
+///
+/// The signals, given with their sample rates, must share t = 0; they are brought to the
+/// lowest of the rates first. Each pair only accepts the delays its `microphones` (m east,
+/// north, up) allow for.
+///
+/// With a `guide`, such as a track fitted to the Doppler curves, the frame of the second
+/// recording is read along the expected delay and only `max_correction` around it is searched;
+/// the frames then hold the same sound even when the delay is long and changes quickly. Without
+/// one, every lag is searched in frames at least four times as long as the longest delay, which
+/// a source moving fast past distant microphones smears.
+pub fn frame_delays(
+    signals: &[(&[f64], u32)],
+    microphones: &[[f64; 3]],
+    config: &TdoaConfig,
+    guide: Option<Guide>,
+) -> Vec<Delay> {
+    let Some(rate) = signals.iter().map(|&(_, rate)| rate).min() else {
+        return vec![];
+    };
+    let rate_f = rate as f64;
+    let signals: Vec<Vec<f64>> = signals
+        .iter()
+        .map(|&(signal, from)| resample(signal, from, rate))
+        .collect();
+    let hop = (config.hop_duration * rate_f).round().max(1.0) as usize;
+
+    let mut planner = FftPlanner::new();
+    let mut delays = vec![];
+    for first in 0..signals.len() {
+        for second in first + 1..signals.len() {
+            let baseline = distance(&microphones[first], &microphones[second]);
+            let max_delay = baseline / MIN_SPEED_OF_SOUND;
+            // both positions are off, in whatever directions
+            let positions = std::f64::consts::SQRT_2 * config.microphone_error / MIN_SPEED_OF_SOUND;
+            let (duration, max_lag) = match guide {
+                Some(_) => (config.frame_duration, config.max_correction.min(max_delay)),
+                None => (config.frame_duration.max(FRAMES_PER_DELAY * max_delay), max_delay),
+            };
+            let frame = (duration * rate_f).round().max(2.0) as usize;
+            let correlator = GccPhat::new(&mut planner, frame);
+            let max_lag = (max_lag * rate_f).ceil() as usize + 2;
+            let (a, b) = (&signals[first], &signals[second]);
+            // frames of all pairs are centred on the same multiples of the hop
+            let mut centre = hop * (frame / 2).div_ceil(hop);
+            while centre + frame - frame / 2 <= a.len() {
+                let t = centre as f64 / rate_f;
+                let start = centre - frame / 2;
+                centre += hop;
+                // expected delay at the start and end of the frame, linear in between
+                let expected = |sample: usize| {
+                    let time = sample as f64 / rate_f;
+                    guide.map_or(0.0, |guide| guide(first, second, time).clamp(-max_delay, max_delay))
+                };
+                let (early, late) = (expected(start), expected(start + frame));
+                let warped: Option<Vec<f64>> = (0..frame)
+                    .map(|i| {
+                        let shift = early + (late - early) * i as f64 / frame as f64;
+                        let position = (start + i) as f64 + shift * rate_f;
+                        let index = position.floor();
+                        let fraction = position - index;
+                        let index = usize::try_from(index as isize).ok()?;
+                        Some(b.get(index)? * (1.0 - fraction) + b.get(index + 1)? * fraction)
+                    })
+                    .collect();
+                let Some(warped) = warped else {
+                    continue;
+                };
+                let Some(peak) = correlator.peak(&a[start..start + frame], &warped, max_lag) else {
+                    continue;
+                };
+                let delay = (early + late) / 2.0 + peak.lag / rate_f;
+                if peak.height >= config.min_coherence && delay.abs() <= max_delay {
+                    let measured = peak.width / (rate_f * peak.height);
+                    delays.push(Delay {
+                        t,
+                        first,
+                        second,
+                        delay,
+                        uncertainty: measured.hypot(positions),
+                        coherence: peak.height,
+                    });
+                }
+            }
+        }
+    }
+    delays
+}
+
+pub(crate) fn distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Angle (degrees) between the baseline from the first to the second microphone of `delay` and
+/// the direction to the source, for a source far away compared to the baseline.
+pub fn bearing(delay: &Delay, microphones: &[[f64; 3]], speed_of_sound: f64) -> f64 {
+    let baseline = distance(&microphones[delay.first], &microphones[delay.second]);
+    // the sound reaches the second microphone later when the source is behind the first one
+    (-delay.delay * speed_of_sound / baseline).clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+/// Position found from the delays of one frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+    pub t: f64,
+    /// m east, north and up.
+    pub position: [f64; 3],
+    /// Root mean square of the delay residuals, s.
+    pub rms: f64,
+}
+
+// weighted squared delay residuals over the unknown coordinates of the source
+struct Multilateration<'a> {
+    delays: &'a [Delay],
+    microphones: &'a [[f64; 3]],
+    speed_of_sound: f64,
+    altitude: Altitude,
+}
+
+impl Multilateration<'_> {
+    fn position(&self, x: &[f64]) -> [f64; 3] {
+        match self.altitude {
+            Altitude::Known(up) => [x[0], x[1], up],
+            Altitude::Fitted => [x[0], x[1], x[2]],
+        }
+    }
+
+    fn residuals(&self, x: &[f64]) -> impl Iterator<Item = f64> + '_ {
+        let source = self.position(x);
+        self.delays.iter().map(move |delay| {
+            let expected = (distance(&source, &self.microphones[delay.second])
+                - distance(&source, &self.microphones[delay.first]))
+                / self.speed_of_sound;
+            (expected - delay.delay) / delay.uncertainty
+        })
+    }
+}
+
+impl Problem for Multilateration<'_> {
+    type Field = f64;
+
+    fn domain(&self) -> Domain<Self::Field> {
+        Domain::unconstrained(match self.altitude {
+            Altitude::Known(_) => 2,
+            Altitude::Fitted => 3,
+        })
+    }
+}
+
+impl Function for Multilateration<'_> {
+    fn apply<Sx>(&self, x: &na::Vector<Self::Field, Dyn, Sx>) -> Self::Field
+    where
+        Sx: na::Storage<Self::Field, Dyn> + IsContiguous,
+    {
+        self.residuals(x.as_slice()).map(|residual| residual * residual).sum()
+    }
+}
+
+// iterations of the trust-region search per frame
+const MULTILATERATION_ITERATIONS: usize = 100;
+
+/// Least-squares position of the source from the `delays` of one frame, with the gomez
+/// trust-region solver started at `initial`.
+///
+/// Needs at least as many independent pairs as unknown coordinates, one microphone more than
+/// unknowns: three for a known altitude and four otherwise determine the position exactly, and
+/// only further microphones leave a residual to judge it by. Where the hyperbolas cross twice,
+/// the start picks the crossing.
+pub fn multilaterate(
+    delays: &[Delay],
+    microphones: &[[f64; 3]],
+    speed_of_sound: f64,
+    altitude: Altitude,
+    initial: [f64; 3],
+) -> Option<Fix> {
+    let unknowns = match altitude {
+        Altitude::Known(_) => 2,
+        Altitude::Fitted => 3,
+    };
+    let mut heard: Vec<usize> = delays.iter().flat_map(|delay| [delay.first, delay.second]).collect();
+    heard.sort_unstable();
+    heard.dedup();
+    if heard.len() <= unknowns {
+        return None;
+    }
+    let problem = Multilateration {
+        delays,
+        microphones,
+        speed_of_sound,
+        altitude,
+    };
+    let mut best = initial[..unknowns].to_vec();
+    let mut best_objective: f64 = problem.residuals(&best).map(|residual| residual * residual).sum();
+    let mut optimizer = OptimizerDriver::builder(&problem)
+        .with_initial(best.clone())
+        .build();
+    // the trust region gives up with an error once it can't improve any more
+    for _ in 0..MULTILATERATION_ITERATIONS {
+        let Ok((x, objective)) = optimizer.next() else {
+            break;
+        };
+        if objective < best_objective {
+            best = x.to_vec();
+            best_objective = objective;
+        }
+    }
+    if !best_objective.is_finite() {
+        return None;
+    }
+    let rms = (problem.residuals(&best).zip(delays).map(|(r, d)| (r * d.uncertainty).powi(2)).sum::<f64>()
+        / delays.len() as f64)
+        .sqrt();
+    Some(Fix {
+        t: delays[0].t,
+        position: problem.position(&best),
+        rms,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    // white noise from a linear congruential generator, in [-0.5, 0.5)
+    fn noise(len: usize) -> Vec<f64> {
+        let mut state = 7u64;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (state >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_an_integer_delay() {
+        let source = noise(1100);
+        let (first, second) = (&source[50..1074], &source[43..1067]);
+        let peak = GccPhat::new(&mut FftPlanner::new(), 1024).peak(first, second, 40).unwrap();
+        assert!((peak.lag - 7.0).abs() < 0.05, "{:?}", peak);
+        assert!(peak.height > 0.5, "{:?}", peak);
+    }
+
+    #[test]
+    fn finds_a_fractional_delay() {
+        // sinusoids all over the band, which can be delayed by any time exactly
+        let components: Vec<(f64, f64)> = noise(1000).chunks(2).map(|pair| ((pair[0] + 0.5) * 0.5, 2.0 * PI * pair[1])).collect();
+        let signal = |t: f64| components.iter().map(|(frequency, phase)| (2.0 * PI * frequency * t + phase).sin()).sum::<f64>();
+        let first: Vec<f64> = (0..1024).map(|i| signal(i as f64)).collect();
+        let second: Vec<f64> = (0..1024).map(|i| signal(i as f64 - 3.4)).collect();
+        let peak = GccPhat::new(&mut FftPlanner::new(), 1024).peak(&first, &second, 40).unwrap();
+        // the parabola leans towards the nearest sample, but far less than rounding would
+        assert!((peak.lag - 3.4).abs() < 0.15, "{:?}", peak);
+    }
+}
@@ -0,0 +1,769 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+use rustfft::num_complex::Complex;
+
+use crate::spectrum::{Hz, Spectrogram};
+
+// half-width of the band whose energy decides which peak wins
+const SUM_STEP: usize = 5;
+
+/// How the peak location is refined below the bin spacing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PeakInterpolation {
+    /// Centre of the strongest bin, no refinement.
+    None,
+    /// Parabola through the magnitudes of the peak bin and its neighbours.
+    #[default]
+    Parabolic,
+    /// Parabola through the log-magnitudes; exact for a Gaussian-shaped peak.
+    Gaussian,
+    /// Quinn's second estimator on the complex spectrum. Unbiased for a rectangular window.
+    Quinn,
+    /// Instantaneous frequency from the phase advance between consecutive STFT frames.
+    PhaseVocoder,
+}
+
+/// Tracked frequency of one frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPoint {
+    /// Centre of the frame, seconds from the start of the recording.
+    pub time: f64,
+    pub frequency: Hz,
+    /// Share of the frame's energy inside the band around the peak, in [0, 1].
+    pub confidence: f64,
+    /// Standard deviation of `frequency`, for weighting the fit.
+    pub uncertainty: Hz,
+}
+
+/// One point per time: points sharing a time, such as the harmonics of one frame, are replaced
+/// by the one with the median frequency. Expects the points in time order.
+pub fn merge_simultaneous(points: &[TrackPoint]) -> Vec<TrackPoint> {
+    points
+        .chunk_by(|a, b| a.time == b.time)
+        .map(|group| {
+            let mut group = group.to_vec();
+            group.sort_by(|a, b| a.frequency.0.total_cmp(&b.frequency.0));
+            group[group.len() / 2]
+        })
+        .collect()
+}
+
+/// Dominant frequency of every spectrum: the centre of the ±5-bin band with the most energy.
+pub fn get_frequencies(spectrogram: &Spectrogram, band: Option<(Hz, Hz)>) -> Vec<Hz> {
+    track_peaks(spectrogram, band, PeakInterpolation::None)
+        .into_iter()
+        .map(|point| point.frequency)
+        .collect()
+}
+
+/// Finds the ±5-bin band with the most energy in every frame and locates the peak inside it
+/// with sub-bin precision. With a search `band` (lo, hi) only peaks inside it are considered,
+/// so rumble and hiss outside it can't win.
+///
+/// `Quinn` and `PhaseVocoder` need the complex spectra; on a smoothed spectrogram they fall
+/// back to `Parabolic`.
+pub fn track_peaks(
+    spectrogram: &Spectrogram,
+    band: Option<(Hz, Hz)>,
+    interpolation: PeakInterpolation,
+) -> Vec<TrackPoint> {
+    let interpolation = available(spectrogram, interpolation);
+    let (first, last) = match band {
+        Some((lo, hi)) => (spectrogram.bin_of(lo), spectrogram.bin_of(hi)),
+        None => (0, spectrogram.bins() - 1),
+    };
+    let mut track = vec![];
+    for (frame, distribution) in spectrogram.magnitudes.iter().enumerate() {
+        let (band_centre, band_sum) = strongest_band(&distribution[first..=last.max(first)]);
+        let band_centre = first + band_centre;
+        let total: f64 = distribution.iter().sum();
+        let confidence = if total > 0.0 { band_sum / total } else { 0.0 };
+
+        // the largest bin of the winning band is the actual peak
+        let lo = band_centre.saturating_sub(SUM_STEP).max(first);
+        let hi = (last + 1).min(band_centre + SUM_STEP).max(lo + 1);
+        let peak = (lo..hi)
+            .max_by(|a, b| distribution[*a].total_cmp(&distribution[*b]))
+            .unwrap_or(band_centre);
+
+        let bin = match interpolation {
+            PeakInterpolation::None => band_centre as f64,
+            _ => refine(spectrogram, frame, distribution, peak, interpolation),
+        };
+        track.push(TrackPoint {
+            time: spectrogram.times[frame],
+            frequency: spectrogram.frequency(bin),
+            confidence,
+            uncertainty: peak_uncertainty(spectrogram, distribution, peak),
+        });
+    }
+    track
+}
+
+// phase-based estimators need the complex spectra, which smoothing throws away
+fn available(spectrogram: &Spectrogram, interpolation: PeakInterpolation) -> PeakInterpolation {
+    match interpolation {
+        PeakInterpolation::Quinn | PeakInterpolation::PhaseVocoder
+            if spectrogram.spectra.len() != spectrogram.len() =>
+        {
+            PeakInterpolation::Parabolic
+        }
+        interpolation => interpolation,
+    }
+}
+
+// fractional bin of the peak at bin k of the given frame
+fn refine(
+    spectrogram: &Spectrogram,
+    frame: usize,
+    distribution: &[f64],
+    k: usize,
+    interpolation: PeakInterpolation,
+) -> f64 {
+    match interpolation {
+        PeakInterpolation::None => k as f64,
+        PeakInterpolation::Parabolic => k as f64 + parabolic(distribution, k, |m| m),
+        PeakInterpolation::Gaussian => {
+            k as f64 + parabolic(distribution, k, |m| m.max(f64::MIN_POSITIVE).ln())
+        }
+        PeakInterpolation::Quinn => k as f64 + quinn(&spectrogram.spectra[frame], k),
+        PeakInterpolation::PhaseVocoder => phase_vocoder(spectrogram, frame, k),
+    }
+}
+
+// Standard deviation of a peak location: the bin width scaled down by the peak's amplitude over
+// the frame's median (noise) level, but never below 5% of a bin.
+fn peak_uncertainty(spectrogram: &Spectrogram, distribution: &[f64], peak: usize) -> Hz {
+    let mut sorted = distribution.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let noise = sorted[sorted.len() / 2];
+    let ratio = if noise > 0.0 {
+        distribution[peak] / noise
+    } else {
+        f64::INFINITY
+    };
+    Hz(spectrogram.bin_width().0 * ratio.max(1.0).recip().max(0.05))
+}
+
+// centre and energy of the ±SUM_STEP band with the highest sum
+fn strongest_band(distribution: &[f64]) -> (usize, f64) {
+    let mut biggest_impact: usize = 0;
+    let mut biggest_sum = 0f64;
+    for i in 0..distribution.len() {
+        let lo = i.saturating_sub(SUM_STEP);
+        let hi = distribution.len().min(i + SUM_STEP);
+        let current_sum: f64 = distribution[lo..hi].iter().sum();
+        if current_sum > biggest_sum {
+            biggest_impact = i;
+            biggest_sum = current_sum;
+        }
+    }
+    (biggest_impact, biggest_sum)
+}
+
+// vertex offset of the parabola through (k-1, k, k+1), in bins
+fn parabolic(distribution: &[f64], k: usize, scale: impl Fn(f64) -> f64) -> f64 {
+    if k == 0 || k + 1 >= distribution.len() {
+        return 0.0;
+    }
+    let (a, b, c) = (
+        scale(distribution[k - 1]),
+        scale(distribution[k]),
+        scale(distribution[k + 1]),
+    );
+    let denominator = a - 2.0 * b + c;
+    if denominator.abs() < f64::EPSILON {
+        return 0.0;
+    }
+    (0.5 * (a - c) / denominator).clamp(-0.5, 0.5)
+}
+
+// Quinn's second estimator, offset in bins
+fn quinn(spectrum: &[Complex<f64>], k: usize) -> f64 {
+    if k == 0 || k + 1 >= spectrum.len() || spectrum[k].norm_sqr() == 0.0 {
+        return 0.0;
+    }
+    let tau = |x: f64| -> f64 {
+        let root = (2.0f64 / 3.0).sqrt();
+        0.25 * (3.0 * x * x + 6.0 * x + 1.0).ln()
+            - 6f64.sqrt() / 24.0 * ((x + 1.0 - root) / (x + 1.0 + root)).ln()
+    };
+    let ap = (spectrum[k + 1] / spectrum[k]).re;
+    let am = (spectrum[k - 1] / spectrum[k]).re;
+    let dp = -ap / (1.0 - ap);
+    let dm = am / (1.0 - am);
+    let delta = (dp + dm) / 2.0 + tau(dp * dp) - tau(dm * dm);
+    if delta.is_finite() {
+        delta.clamp(-0.5, 0.5)
+    } else {
+        0.0
+    }
+}
+
+// instantaneous frequency of bin k, as a fractional bin
+fn phase_vocoder(spectrogram: &Spectrogram, frame: usize, k: usize) -> f64 {
+    // compare with the previous frame, or the next one for the very first frame
+    let (earlier, later) = match frame {
+        0 if spectrogram.len() < 2 => return k as f64,
+        0 => (0, 1),
+        _ => (frame - 1, frame),
+    };
+    let hop = spectrogram.hop_duration * spectrogram.sample_rate as f64;
+    let n = spectrogram.fft_len as f64;
+
+    let expected = 2.0 * PI * k as f64 * hop / n;
+    let measured = spectrogram.spectra[later][k].arg() - spectrogram.spectra[earlier][k].arg();
+    // deviation from the bin centre's phase advance, wrapped to [-pi, pi)
+    let deviation = (measured - expected + PI).rem_euclid(2.0 * PI) - PI;
+
+    k as f64 + deviation * n / (2.0 * PI * hop)
+}
+
+/// How the harmonics of a candidate fundamental are combined into its score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HarmonicMethod {
+    /// Harmonic sum spectrum: robust when some harmonics are missing.
+    #[default]
+    Sum,
+    /// Harmonic product spectrum: sharper, but one weak harmonic vetoes the candidate.
+    Product,
+}
+
+/// Settings of the harmonic-aware fundamental tracker.
+#[derive(Debug, Clone, Copy)]
+pub struct HarmonicConfig {
+    pub method: HarmonicMethod,
+    /// Number of harmonics scored, the fundamental included.
+    pub harmonics: usize,
+    /// Search range of the fundamental.
+    pub min_fundamental: Hz,
+    pub max_fundamental: Hz,
+}
+
+impl Default for HarmonicConfig {
+    fn default() -> Self {
+        HarmonicConfig {
+            method: HarmonicMethod::Sum,
+            harmonics: 5,
+            min_fundamental: Hz(40.0),
+            max_fundamental: Hz(500.0),
+        }
+    }
+}
+
+/// Result of harmonic tracking.
+#[derive(Debug, Clone)]
+pub struct HarmonicTrack {
+    /// Fundamental of every frame, combined from all harmonics.
+    pub fundamental: Vec<TrackPoint>,
+    /// `harmonics[h - 1][frame]`: measured track of the h-th harmonic (h = 1 is the fundamental
+    /// itself). Every harmonic carries the same relative Doppler shift.
+    pub harmonics: Vec<Vec<TrackPoint>>,
+}
+
+impl HarmonicTrack {
+    /// Track of the h-th harmonic divided by h, i.e. that harmonic's estimate of the fundamental.
+    pub fn normalised(&self, harmonic: usize) -> Vec<TrackPoint> {
+        self.harmonics[harmonic - 1]
+            .iter()
+            .map(|point| TrackPoint {
+                frequency: Hz(point.frequency.0 / harmonic as f64),
+                uncertainty: Hz(point.uncertainty.0 / harmonic as f64),
+                ..*point
+            })
+            .collect()
+    }
+}
+
+/// Estimates the fundamental of a harmonic source in every frame from all its harmonics at once,
+/// so the track doesn't jump between harmonics when their relative strength changes.
+///
+/// Each harmonic is then located separately near its expected bin and refined with
+/// `interpolation` (with the same fallback as `track_peaks`); the fundamental is the
+/// magnitude-weighted mean of the harmonics divided by their order.
+///
+/// Fails if every candidate in the search range has harmonics beyond the top of the spectrum.
+pub fn track_fundamental(
+    spectrogram: &Spectrogram,
+    config: &HarmonicConfig,
+    interpolation: PeakInterpolation,
+) -> Result<HarmonicTrack, HarmonicsOutOfRange> {
+    let interpolation = available(spectrogram, interpolation);
+    let harmonics = config.harmonics.max(1);
+    // every scored harmonic of the candidate has to fit into the spectrum
+    let highest = (spectrogram.bins() - 1) / harmonics;
+    let min_bin = spectrogram.bin_of(config.min_fundamental).max(1);
+    if min_bin > highest {
+        return Err(HarmonicsOutOfRange {
+            harmonics,
+            highest: spectrogram.frequency(highest as f64),
+            min_fundamental: config.min_fundamental,
+        });
+    }
+    let max_bin = spectrogram.bin_of(config.max_fundamental).min(highest).max(min_bin);
+
+    let mut fundamental = vec![];
+    let mut tracks = vec![vec![]; harmonics];
+    for (frame, distribution) in spectrogram.magnitudes.iter().enumerate() {
+        let score = |k: usize| -> f64 {
+            let values = (1..=harmonics).map(|h| distribution[h * k]);
+            match config.method {
+                HarmonicMethod::Sum => values.sum(),
+                HarmonicMethod::Product => values.map(|m| m.max(f64::MIN_POSITIVE).ln()).sum(),
+            }
+        };
+        let k0 = (min_bin..=max_bin)
+            .max_by(|a, b| score(*a).total_cmp(&score(*b)))
+            .unwrap_or(min_bin);
+
+        let total: f64 = distribution.iter().sum();
+        let mut captured = vec![false; distribution.len()];
+        let mut weighted_sum = 0.0;
+        let mut weight = 0.0;
+        let mut information = 0.0;
+        for h in 1..=harmonics {
+            // k0 is only good to half a bin, which the h-th harmonic multiplies to h / 2 bins
+            // either side of h * k0; at least the neighbouring bins are always searched
+            let reach = (h / 2).max(1);
+            let lo = (h * k0).saturating_sub(reach);
+            let hi = (h * k0 + reach).min(distribution.len() - 1);
+            let peak = (lo..=hi)
+                .max_by(|a, b| distribution[*a].total_cmp(&distribution[*b]))
+                .unwrap_or(h * k0);
+            let bin = refine(spectrogram, frame, distribution, peak, interpolation);
+
+            let lo = peak.saturating_sub(1);
+            let hi = (peak + 1).min(distribution.len() - 1);
+            captured[lo..=hi].iter_mut().for_each(|c| *c = true);
+            let energy: f64 = distribution[lo..=hi].iter().sum();
+
+            let uncertainty = peak_uncertainty(spectrogram, distribution, peak);
+            tracks[h - 1].push(TrackPoint {
+                time: spectrogram.times[frame],
+                frequency: spectrogram.frequency(bin),
+                confidence: if total > 0.0 { energy / total } else { 0.0 },
+                uncertainty,
+            });
+            information += (h as f64 / uncertainty.0).powi(2);
+            weighted_sum += distribution[peak] * bin / h as f64;
+            weight += distribution[peak];
+        }
+
+        let captured_energy: f64 = distribution
+            .iter()
+            .zip(&captured)
+            .filter(|(_, c)| **c)
+            .map(|(m, _)| m)
+            .sum();
+        fundamental.push(TrackPoint {
+            time: spectrogram.times[frame],
+            frequency: spectrogram.frequency(if weight > 0.0 {
+                weighted_sum / weight
+            } else {
+                k0 as f64
+            }),
+            confidence: if total > 0.0 { captured_energy / total } else { 0.0 },
+            uncertainty: Hz(information.sqrt().recip()),
+        });
+    }
+
+    Ok(HarmonicTrack {
+        fundamental,
+        harmonics: tracks,
+    })
+}
+
+/// Returned when no fundamental of the search range has all its harmonics inside the spectrum.
+#[derive(Debug, Clone, Copy)]
+pub struct HarmonicsOutOfRange {
+    pub harmonics: usize,
+    /// Highest fundamental whose harmonics all fit.
+    pub highest: Hz,
+    pub min_fundamental: Hz,
+}
+
+impl fmt::Display for HarmonicsOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "with {} harmonics the fundamental can be at most {}, below the search minimum of {}",
+            self.harmonics, self.highest, self.min_fundamental
+        )
+    }
+}
+
+impl std::error::Error for HarmonicsOutOfRange {}
+
+/// Settings of the continuity-constrained (Viterbi) tracker.
+#[derive(Debug, Clone, Copy)]
+pub struct ViterbiConfig {
+    /// Only frequencies inside this band are considered.
+    pub min_frequency: Hz,
+    pub max_frequency: Hz,
+    /// Typical rate of change of the tracked frequency, Hz per second. A jump of one `slew`
+    /// costs as much as a factor of e in magnitude; jumps above three `slew`s are forbidden.
+    pub slew: f64,
+}
+
+impl Default for ViterbiConfig {
+    fn default() -> Self {
+        ViterbiConfig {
+            min_frequency: Hz(40.0),
+            max_frequency: Hz(2000.0),
+            slew: 50.0,
+        }
+    }
+}
+
+/// Finds the smoothest high-energy path through the spectrogram with dynamic programming.
+///
+/// Every frame scores its bins by log-magnitude and every step between frames is penalised by
+/// the square of the frequency change, so a single noisy frame can't pull the track away.
+pub fn track_viterbi(
+    spectrogram: &Spectrogram,
+    config: &ViterbiConfig,
+    interpolation: PeakInterpolation,
+) -> Vec<TrackPoint> {
+    if spectrogram.is_empty() {
+        return vec![];
+    }
+    let interpolation = available(spectrogram, interpolation);
+    let lo = spectrogram.bin_of(config.min_frequency);
+    let hi = spectrogram.bin_of(config.max_frequency).max(lo);
+    let states = hi - lo + 1;
+
+    // allowed change per frame, in bins
+    let step = config.slew * spectrogram.hop_duration / spectrogram.bin_width().0;
+    let reach = ((3.0 * step).ceil() as usize).max(1);
+    let transition = |from: usize, to: usize| -> f64 {
+        let jump = from.abs_diff(to) as f64;
+        (jump / step.max(f64::MIN_POSITIVE)).powi(2)
+    };
+    let emission = |distribution: &[f64]| -> Vec<f64> {
+        let max = distribution[lo..=hi].iter().cloned().fold(0.0, f64::max);
+        distribution[lo..=hi]
+            .iter()
+            .map(|m| (m / max).max(1e-12).ln())
+            .collect()
+    };
+
+    let mut score = emission(&spectrogram.magnitudes[0]);
+    let mut back: Vec<Vec<usize>> = vec![];
+    for distribution in &spectrogram.magnitudes[1..] {
+        let observed = emission(distribution);
+        let mut next = vec![f64::NEG_INFINITY; states];
+        let mut from = vec![0; states];
+        for to in 0..states {
+            let start = to.saturating_sub(reach);
+            let end = (to + reach).min(states - 1);
+            for (previous, value) in score.iter().enumerate().take(end + 1).skip(start) {
+                let candidate = value - transition(previous, to);
+                if candidate > next[to] {
+                    next[to] = candidate;
+                    from[to] = previous;
+                }
+            }
+            next[to] += observed[to];
+        }
+        score = next;
+        back.push(from);
+    }
+
+    let mut state = (0..states)
+        .max_by(|a, b| score[*a].total_cmp(&score[*b]))
+        .unwrap_or(0);
+    let mut path = vec![state];
+    for from in back.iter().rev() {
+        state = from[state];
+        path.push(state);
+    }
+    path.reverse();
+
+    path.into_iter()
+        .enumerate()
+        .map(|(frame, state)| {
+            let distribution = &spectrogram.magnitudes[frame];
+            let peak = lo + state;
+            let bin = refine(spectrogram, frame, distribution, peak, interpolation);
+            let total: f64 = distribution.iter().sum();
+            let energy: f64 =
+                distribution[peak.saturating_sub(1)..=(peak + 1).min(distribution.len() - 1)]
+                    .iter()
+                    .sum();
+            TrackPoint {
+                time: spectrogram.times[frame],
+                frequency: spectrogram.frequency(bin),
+                confidence: if total > 0.0 { energy / total } else { 0.0 },
+                uncertainty: peak_uncertainty(spectrogram, distribution, peak),
+            }
+        })
+        .collect()
+}
+
+/// Settings of the Kalman smoother.
+#[derive(Debug, Clone, Copy)]
+pub struct KalmanConfig {
+    /// Spectral density of the random frequency acceleration, (Hz/s²)² per Hz.
+    pub process_noise: f64,
+    /// Measurements further than this many standard deviations from the prediction are treated
+    /// as outliers and their uncertainty is inflated accordingly.
+    pub gate: f64,
+}
+
+impl Default for KalmanConfig {
+    fn default() -> Self {
+        KalmanConfig {
+            process_noise: 100.0,
+            gate: 3.0,
+        }
+    }
+}
+
+/// Smooths a frequency track with a constant-rate Kalman filter on (frequency, rate of change)
+/// followed by a Rauch–Tung–Striebel pass. Each step predicts over the time between its two
+/// points, so missing frames only widen the prediction.
+///
+/// Each point's `uncertainty` is used as its measurement noise; the result carries the
+/// posterior standard deviation instead.
+pub fn kalman_smooth(track: &[TrackPoint], config: &KalmanConfig) -> Vec<TrackPoint> {
+    type State = [f64; 2];
+    type Covariance = [[f64; 2]; 2];
+
+    let Some(first) = track.first() else {
+        return vec![];
+    };
+    let q = config.process_noise;
+    // F = [[1, dt], [0, 1]], Q for white acceleration noise
+    let predict = |x: &State, p: &Covariance, dt: f64| -> (State, Covariance) {
+        let process: Covariance = [
+            [q * dt.powi(3) / 3.0, q * dt.powi(2) / 2.0],
+            [q * dt.powi(2) / 2.0, q * dt],
+        ];
+        let x = [x[0] + dt * x[1], x[1]];
+        let p = [
+            [
+                p[0][0] + dt * (p[0][1] + p[1][0]) + dt * dt * p[1][1] + process[0][0],
+                p[0][1] + dt * p[1][1] + process[0][1],
+            ],
+            [p[1][0] + dt * p[1][1] + process[1][0], p[1][1] + process[1][1]],
+        ];
+        (x, p)
+    };
+
+    let mut x: State = [first.frequency.0, 0.0];
+    let mut p: Covariance = [[first.uncertainty.0.powi(2), 0.0], [0.0, 1e4]];
+    let mut filtered = Vec::with_capacity(track.len());
+    let mut predicted = Vec::with_capacity(track.len());
+    for (i, point) in track.iter().enumerate() {
+        let (xp, pp) = if i == 0 { (x, p) } else { predict(&x, &p, point.time - track[i - 1].time) };
+        predicted.push((xp, pp));
+
+        let innovation = point.frequency.0 - xp[0];
+        let mut r = point.uncertainty.0.powi(2).max(f64::MIN_POSITIVE);
+        let s = pp[0][0] + r;
+        let distance = innovation.abs() / s.sqrt();
+        if distance > config.gate {
+            r *= (distance / config.gate).powi(2);
+        }
+        let s = pp[0][0] + r;
+        let gain = [pp[0][0] / s, pp[1][0] / s];
+        x = [xp[0] + gain[0] * innovation, xp[1] + gain[1] * innovation];
+        p = [
+            [(1.0 - gain[0]) * pp[0][0], (1.0 - gain[0]) * pp[0][1]],
+            [pp[1][0] - gain[1] * pp[0][0], pp[1][1] - gain[1] * pp[0][1]],
+        ];
+        filtered.push((x, p));
+    }
+
+    // backward pass
+    let mut smoothed = filtered.clone();
+    for i in (0..track.len().saturating_sub(1)).rev() {
+        let (xf, pf) = filtered[i];
+        let (xp, pp) = predicted[i + 1];
+        let (xs, ps) = smoothed[i + 1];
+        let dt = track[i + 1].time - track[i].time;
+        // C = Pf F^T Pp^-1
+        let pf_ft = [
+            [pf[0][0] + dt * pf[0][1], pf[0][1]],
+            [pf[1][0] + dt * pf[1][1], pf[1][1]],
+        ];
+        let det = pp[0][0] * pp[1][1] - pp[0][1] * pp[1][0];
+        if det.abs() < f64::MIN_POSITIVE {
+            continue;
+        }
+        let inv = [
+            [pp[1][1] / det, -pp[0][1] / det],
+            [-pp[1][0] / det, pp[0][0] / det],
+        ];
+        let c = [
+            [
+                pf_ft[0][0] * inv[0][0] + pf_ft[0][1] * inv[1][0],
+                pf_ft[0][0] * inv[0][1] + pf_ft[0][1] * inv[1][1],
+            ],
+            [
+                pf_ft[1][0] * inv[0][0] + pf_ft[1][1] * inv[1][0],
+                pf_ft[1][0] * inv[0][1] + pf_ft[1][1] * inv[1][1],
+            ],
+        ];
+        let dx = [xs[0] - xp[0], xs[1] - xp[1]];
+        let dp = [
+            [ps[0][0] - pp[0][0], ps[0][1] - pp[0][1]],
+            [ps[1][0] - pp[1][0], ps[1][1] - pp[1][1]],
+        ];
+        let x = [
+            xf[0] + c[0][0] * dx[0] + c[0][1] * dx[1],
+            xf[1] + c[1][0] * dx[0] + c[1][1] * dx[1],
+        ];
+        // P = Pf + C dP C^T, only the frequency variance is reported but the rest is propagated
+        let mut p = pf;
+        for (r, row) in p.iter_mut().enumerate() {
+            for (col, value) in row.iter_mut().enumerate() {
+                for a in 0..2 {
+                    for b in 0..2 {
+                        *value += c[r][a] * dp[a][b] * c[col][b];
+                    }
+                }
+            }
+        }
+        smoothed[i] = (x, p);
+    }
+
+    track
+        .iter()
+        .zip(smoothed)
+        .map(|(point, (x, p))| TrackPoint {
+            frequency: Hz(x[0]),
+            uncertainty: Hz(p[0][0].max(0.0).sqrt()),
+            ..*point
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::{stft, StftConfig, Window};
+
+    // a tone 0.3 bins above bin 128, in one frame of 1024 samples at 8000 Hz
+    fn off_bin_tone(window: Window) -> (Spectrogram, f64) {
+        let frequency = (128.0 + 0.3) * 8000.0 / 1024.0;
+        let signal: Vec<f64> = (0..1024).map(|i| (2.0 * PI * frequency * i as f64 / 8000.0).cos()).collect();
+        let config = StftConfig {
+            frame_len: 1024,
+            hop: 1024,
+            window,
+            fft_len: None,
+        };
+        (stft(&signal, 8000, &config), frequency)
+    }
+
+    // error of the tracked frequency in bins
+    fn error(window: Window, interpolation: PeakInterpolation) -> f64 {
+        let (spectrogram, frequency) = off_bin_tone(window);
+        let track = track_peaks(&spectrogram, None, interpolation);
+        (track[0].frequency.0 - frequency).abs() / spectrogram.bin_width().0
+    }
+
+    #[test]
+    fn refinement_finds_an_off_bin_tone() {
+        // a parabola through the Hann main lobe is off by up to about 0.06 bins, through its
+        // log by a quarter of that
+        assert!(error(Window::Hann, PeakInterpolation::Parabolic) < 0.06);
+        assert!(error(Window::Hann, PeakInterpolation::Gaussian) < 0.02);
+        assert!(error(Window::Rectangular, PeakInterpolation::Quinn) < 0.001);
+        assert!(error(Window::Hann, PeakInterpolation::None) > 0.25);
+    }
+
+    #[test]
+    fn harmonics_must_fit_below_nyquist() {
+        // 129 bins of 31.25 Hz: four harmonics of anything above 1000 Hz run off the end
+        let signal: Vec<f64> = (0..256).map(|i| (2.0 * PI * 1600.0 * i as f64 / 8000.0).sin()).collect();
+        let config = StftConfig {
+            frame_len: 256,
+            hop: 256,
+            window: Window::Hann,
+            fft_len: None,
+        };
+        let spectrogram = stft(&signal, 8000, &config);
+        let harmonic = HarmonicConfig {
+            harmonics: 4,
+            min_fundamental: Hz(1500.0),
+            max_fundamental: Hz(2000.0),
+            ..HarmonicConfig::default()
+        };
+        let error = track_fundamental(&spectrogram, &harmonic, PeakInterpolation::Parabolic).unwrap_err();
+        assert_eq!(error.highest, Hz(1000.0));
+
+        let harmonic = HarmonicConfig {
+            harmonics: 2,
+            ..harmonic
+        };
+        let track = track_fundamental(&spectrogram, &harmonic, PeakInterpolation::Parabolic).unwrap();
+        assert!((track.harmonics[0][0].frequency.0 - 1600.0).abs() < 31.25, "{:?}", track.harmonics[0][0]);
+    }
+
+    // a steady tone on bin 20 over a flat floor, 32 ms frames of 31.25 Hz bins
+    fn tone(frames: usize) -> Spectrogram {
+        let mut magnitudes = vec![vec![0.01; 129]; frames];
+        for distribution in &mut magnitudes {
+            distribution[19] = 0.5;
+            distribution[20] = 1.0;
+            distribution[21] = 0.5;
+        }
+        Spectrogram {
+            magnitudes,
+            spectra: vec![],
+            times: (0..frames).map(|frame| 0.016 + 0.032 * frame as f64).collect(),
+            hop_duration: 0.032,
+            sample_rate: 8000,
+            fft_len: 256,
+        }
+    }
+
+    #[test]
+    fn viterbi_ignores_a_loud_frame_elsewhere() {
+        let mut spectrogram = tone(20);
+        spectrogram.magnitudes[10][60] = 3.0;
+        // the plain peak tracker jumps to it
+        assert!(track_peaks(&spectrogram, None, PeakInterpolation::None)[10].frequency.0 > 1000.0);
+        let track = track_viterbi(&spectrogram, &ViterbiConfig::default(), PeakInterpolation::None);
+        assert!(track.iter().all(|point| point.frequency == Hz(20.0 * 31.25)), "{:?}", track[10]);
+    }
+
+    fn point(time: f64, frequency: f64) -> TrackPoint {
+        TrackPoint {
+            time,
+            frequency: Hz(frequency),
+            confidence: 1.0,
+            uncertainty: Hz(5.0),
+        }
+    }
+
+    #[test]
+    fn kalman_narrows_a_noisy_track() {
+        let track: Vec<TrackPoint> =
+            (0..50).map(|i| point(0.1 * i as f64, 500.0 + if i % 2 == 0 { 5.0 } else { -5.0 })).collect();
+        let smoothed = kalman_smooth(&track, &KalmanConfig::default());
+        for point in &smoothed[5..45] {
+            assert!((point.frequency.0 - 500.0).abs() < 2.0, "{:?}", point);
+            assert!(point.uncertainty.0 < 2.5, "{:?}", point);
+        }
+    }
+
+    #[test]
+    fn kalman_steps_over_missing_frames() {
+        // a steady rise of 20 Hz/s with the frames from 1 s to 2 s missing
+        let track: Vec<TrackPoint> = (0..30)
+            .filter(|i| !(10..20).contains(i))
+            .map(|i| point(0.1 * i as f64, 500.0 + 2.0 * i as f64))
+            .collect();
+        let config = KalmanConfig {
+            process_noise: 1.0,
+            ..KalmanConfig::default()
+        };
+        for (point, smoothed) in track.iter().zip(kalman_smooth(&track, &config)) {
+            assert!((smoothed.frequency.0 - point.frequency.0).abs() < 0.5, "{:?} against {:?}", smoothed, point);
+        }
+    }
+}
@@ -0,0 +1,588 @@
+use crate::guess::PassEstimate;
+
+/// Where the source is and how it moves at one instant, relative to the microphone: x along the
+/// track at closest approach, y across it on the ground, z up, in m, m/s and m/s².
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    pub position: [f64; 3],
+    pub velocity: [f64; 3],
+    pub acceleration: [f64; 3],
+    /// Derivatives of the position over every trajectory parameter, in parameter order.
+    pub d_position: Vec<[f64; 3]>,
+    /// Derivatives of the velocity over every trajectory parameter, in parameter order.
+    pub d_velocity: Vec<[f64; 3]>,
+}
+
+/// Path of the source over time.
+///
+/// Every trajectory starts its parameters with a distance, the speed and the time of closest
+/// approach t_closest, at which the source crosses x = 0 heading along +x; further parameters
+/// follow.
+pub trait TrajectoryModel {
+    fn name(&self) -> &'static str;
+
+    fn parameter_names(&self) -> &'static [&'static str];
+
+    fn units(&self) -> &'static [&'static str];
+
+    /// Position, velocity and acceleration at time t.
+    fn state(&self, params: &[f64], t: f64) -> State;
+
+    /// Miss distance (m).
+    fn distance(&self, params: &[f64]) -> f64 {
+        params[0].abs()
+    }
+
+    /// Parameters of the pass described by `estimate`.
+    fn guess(&self, estimate: &PassEstimate) -> Vec<f64>;
+
+    /// Box the gomez solvers are confined to, with speeds up to `max_speed`.
+    fn domain(&self, max_speed: f64) -> Vec<(f64, f64)>;
+
+    /// Plausible ranges for the grid search over a track `duration` seconds long.
+    fn search_ranges(&self, max_speed: f64, duration: f64) -> Vec<(f64, f64)>;
+
+    /// Brings solutions mirrored across the track to one form.
+    fn canonical(&self, params: &mut [f64]);
+
+    /// Turns a source flying towards -x around, returning whether it did. Only equivalent if
+    /// nothing else, i.e. the wind, tells the directions apart.
+    fn reverse(&self, params: &mut [f64]) -> bool;
+
+    /// Pulls the parameters back to where the speed stays within ±`max_speed` from `start` to
+    /// `end`; the domain only bounds the speed at closest approach, which is all there is to
+    /// bound unless the speed changes.
+    fn limit_speed(&self, _params: &mut [f64], _max_speed: f64, _start: f64, _end: f64) {}
+}
+
+/// Which trajectory the classical formula moves the source along.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Trajectory {
+    #[default]
+    Straight,
+    ConstantAcceleration,
+    Turn,
+    AltitudeOffset,
+    KnownAltitude(KnownAltitude),
+    KnownOffset(KnownOffset),
+}
+
+impl Trajectory {
+    /// The trajectories without a known quantity, for model selection; `AltitudeOffset` is left
+    /// out, since one microphone can't tell its altitude from its offset.
+    pub const ALL: [Trajectory; 3] = [Trajectory::Straight, Trajectory::ConstantAcceleration, Trajectory::Turn];
+
+    pub fn model(&self) -> &dyn TrajectoryModel {
+        match self {
+            Trajectory::Straight => &Straight,
+            Trajectory::ConstantAcceleration => &ConstantAcceleration,
+            Trajectory::Turn => &Turn,
+            Trajectory::AltitudeOffset => &AltitudeOffset,
+            Trajectory::KnownAltitude(model) => model,
+            Trajectory::KnownOffset(model) => model,
+        }
+    }
+
+    /// Whether the ground offset and the altitude of the track come out separately, rather
+    /// than as the miss distance alone.
+    pub fn separates_altitude(&self) -> bool {
+        matches!(
+            self,
+            Trajectory::AltitudeOffset | Trajectory::KnownAltitude(_) | Trajectory::KnownOffset(_)
+        )
+    }
+}
+
+const STRAIGHT_PARAMETERS: [&str; 3] = ["d", "v", "t_closest"];
+const STRAIGHT_UNITS: [&str; 3] = ["m", "m/s", "s"];
+const ACCELERATION_PARAMETERS: [&str; 4] = ["d", "v", "t_closest", "a"];
+const ACCELERATION_UNITS: [&str; 4] = ["m", "m/s", "s", "m/s²"];
+const TURN_PARAMETERS: [&str; 4] = ["d", "v", "t_closest", "omega"];
+const TURN_UNITS: [&str; 4] = ["m", "m/s", "s", "rad/s"];
+const ALTITUDE_PARAMETERS: [&str; 4] = ["y", "v", "t_closest", "h"];
+const ALTITUDE_UNITS: [&str; 4] = ["m", "m/s", "s", "m"];
+const KNOWN_ALTITUDE_PARAMETERS: [&str; 3] = ["y", "v", "t_closest"];
+const KNOWN_OFFSET_PARAMETERS: [&str; 3] = ["h", "v", "t_closest"];
+
+// common to all trajectories: distance, speed and time of closest approach
+fn pass_domain(max_speed: f64) -> Vec<(f64, f64)> {
+    vec![(0.0, 100000.0), (0.0, max_speed), (-100000.0, 100000.0)]
+}
+
+fn pass_search_ranges(max_speed: f64, duration: f64) -> Vec<(f64, f64)> {
+    vec![(1.0, 2000.0), (1.0, max_speed), (0.0, duration)]
+}
+
+/// Constant speed v along a straight line at distance d, the original model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Straight;
+
+impl TrajectoryModel for Straight {
+    fn name(&self) -> &'static str {
+        "straight"
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &STRAIGHT_PARAMETERS
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &STRAIGHT_UNITS
+    }
+
+    //   P = (v tau, d, 0),  tau = t - t_closest
+    fn state(&self, params: &[f64], t: f64) -> State {
+        let (d, v, t_closest) = (params[0], params[1], params[2]);
+        let tau = t - t_closest;
+        State {
+            position: [v * tau, d, 0.0],
+            velocity: [v, 0.0, 0.0],
+            acceleration: [0.0; 3],
+            d_position: vec![[0.0, 1.0, 0.0], [tau, 0.0, 0.0], [-v, 0.0, 0.0]],
+            d_velocity: vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0; 3]],
+        }
+    }
+
+    fn guess(&self, estimate: &PassEstimate) -> Vec<f64> {
+        vec![estimate.distance, estimate.speed, estimate.t_closest]
+    }
+
+    fn domain(&self, max_speed: f64) -> Vec<(f64, f64)> {
+        pass_domain(max_speed)
+    }
+
+    fn search_ranges(&self, max_speed: f64, duration: f64) -> Vec<(f64, f64)> {
+        pass_search_ranges(max_speed, duration)
+    }
+
+    fn canonical(&self, params: &mut [f64]) {
+        params[0] = params[0].abs();
+    }
+
+    fn reverse(&self, params: &mut [f64]) -> bool {
+        let reversed = params[1] < 0.0;
+        if reversed {
+            params[1] = -params[1];
+        }
+        reversed
+    }
+}
+
+/// A straight line flown with constant acceleration a; v is the speed at closest approach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConstantAcceleration;
+
+impl TrajectoryModel for ConstantAcceleration {
+    fn name(&self) -> &'static str {
+        "constant acceleration"
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &ACCELERATION_PARAMETERS
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &ACCELERATION_UNITS
+    }
+
+    //   P = (v tau + a tau^2 / 2, d, 0)
+    fn state(&self, params: &[f64], t: f64) -> State {
+        let (d, v, t_closest, a) = (params[0], params[1], params[2], params[3]);
+        let tau = t - t_closest;
+        let speed = v + a * tau;
+        State {
+            position: [v * tau + a * tau * tau / 2.0, d, 0.0],
+            velocity: [speed, 0.0, 0.0],
+            acceleration: [a, 0.0, 0.0],
+            d_position: vec![
+                [0.0, 1.0, 0.0],
+                [tau, 0.0, 0.0],
+                [-speed, 0.0, 0.0],
+                [tau * tau / 2.0, 0.0, 0.0],
+            ],
+            d_velocity: vec![[0.0; 3], [1.0, 0.0, 0.0], [-a, 0.0, 0.0], [tau, 0.0, 0.0]],
+        }
+    }
+
+    fn guess(&self, estimate: &PassEstimate) -> Vec<f64> {
+        vec![estimate.distance, estimate.speed, estimate.t_closest, 0.0]
+    }
+
+    // about 5 g either way, well beyond what the fit should need
+    fn domain(&self, max_speed: f64) -> Vec<(f64, f64)> {
+        let mut domain = pass_domain(max_speed);
+        domain.push((-50.0, 50.0));
+        domain
+    }
+
+    fn search_ranges(&self, max_speed: f64, duration: f64) -> Vec<(f64, f64)> {
+        let mut ranges = pass_search_ranges(max_speed, duration);
+        ranges.push((-10.0, 10.0));
+        ranges
+    }
+
+    fn canonical(&self, params: &mut [f64]) {
+        params[0] = params[0].abs();
+    }
+
+    fn reverse(&self, params: &mut [f64]) -> bool {
+        let reversed = params[1] < 0.0;
+        if reversed {
+            params[1] = -params[1];
+            params[3] = -params[3];
+        }
+        reversed
+    }
+
+    // v + a tau is linear in tau, so both ends of the span bound it everywhere in between
+    fn limit_speed(&self, params: &mut [f64], max_speed: f64, start: f64, end: f64) {
+        let (v, t_closest) = (params[1], params[2]);
+        let (mut lo, mut hi) = (f64::NEG_INFINITY, f64::INFINITY);
+        for tau in [start - t_closest, end - t_closest] {
+            if tau == 0.0 {
+                continue;
+            }
+            let (a, b) = ((-max_speed - v) / tau, (max_speed - v) / tau);
+            lo = lo.max(a.min(b));
+            hi = hi.min(a.max(b));
+        }
+        // empty only if v itself is out of bounds, which the domain takes care of
+        if lo <= hi {
+            params[3] = params[3].clamp(lo, hi);
+        }
+    }
+}
+
+/// A level turn at constant speed v and rate omega, positive towards +y (away from the
+/// microphone when d > 0), heading along +x at closest approach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Turn;
+
+// below this turning angle sin(phi) / phi and friends are taken from their series
+const SMALL_ANGLE: f64 = 1e-3;
+
+// sin(phi) / phi, (1 - cos(phi)) / phi and their derivatives
+fn turn_functions(phi: f64) -> (f64, f64, f64, f64) {
+    if phi.abs() < SMALL_ANGLE {
+        let phi2 = phi * phi;
+        return (
+            1.0 - phi2 / 6.0,
+            phi / 2.0 - phi * phi2 / 24.0,
+            -phi / 3.0 + phi * phi2 / 30.0,
+            0.5 - phi2 / 8.0,
+        );
+    }
+    let (sin, cos) = phi.sin_cos();
+    let phi2 = phi * phi;
+    (
+        sin / phi,
+        (1.0 - cos) / phi,
+        (phi * cos - sin) / phi2,
+        (phi * sin - 1.0 + cos) / phi2,
+    )
+}
+
+impl TrajectoryModel for Turn {
+    fn name(&self) -> &'static str {
+        "turn"
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &TURN_PARAMETERS
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &TURN_UNITS
+    }
+
+    //   P = (v tau S(phi), d + v tau C(phi), 0),  phi = omega tau
+    //   S = sin(phi) / phi,  C = (1 - cos(phi)) / phi
+    fn state(&self, params: &[f64], t: f64) -> State {
+        let (d, v, t_closest, omega) = (params[0], params[1], params[2], params[3]);
+        let tau = t - t_closest;
+        let phi = omega * tau;
+        let (s, c, d_s, d_c) = turn_functions(phi);
+        let (sin, cos) = phi.sin_cos();
+        let velocity = [v * cos, v * sin, 0.0];
+        let acceleration = [-v * omega * sin, v * omega * cos, 0.0];
+        State {
+            position: [v * tau * s, d + v * tau * c, 0.0],
+            velocity,
+            acceleration,
+            d_position: vec![
+                [0.0, 1.0, 0.0],
+                [tau * s, tau * c, 0.0],
+                [-velocity[0], -velocity[1], 0.0],
+                [v * tau * tau * d_s, v * tau * tau * d_c, 0.0],
+            ],
+            d_velocity: vec![
+                [0.0; 3],
+                [cos, sin, 0.0],
+                [-acceleration[0], -acceleration[1], 0.0],
+                [-v * tau * sin, v * tau * cos, 0.0],
+            ],
+        }
+    }
+
+    fn guess(&self, estimate: &PassEstimate) -> Vec<f64> {
+        vec![estimate.distance, estimate.speed, estimate.t_closest, 0.0]
+    }
+
+    // a rate-one turn is 3 degrees per second, this allows about twenty times that
+    fn domain(&self, max_speed: f64) -> Vec<(f64, f64)> {
+        let mut domain = pass_domain(max_speed);
+        domain.push((-1.0, 1.0));
+        domain
+    }
+
+    fn search_ranges(&self, max_speed: f64, duration: f64) -> Vec<(f64, f64)> {
+        let mut ranges = pass_search_ranges(max_speed, duration);
+        ranges.push((-0.3, 0.3));
+        ranges
+    }
+
+    // mirroring y turns the other way round
+    fn canonical(&self, params: &mut [f64]) {
+        if params[0] < 0.0 {
+            params[0] = -params[0];
+            params[3] = -params[3];
+        }
+    }
+
+    fn reverse(&self, params: &mut [f64]) -> bool {
+        let reversed = params[1] < 0.0;
+        if reversed {
+            params[1] = -params[1];
+            params[3] = -params[3];
+        }
+        reversed
+    }
+}
+
+/// A straight level line with the ground offset y and the altitude h in place of the miss
+/// distance d = sqrt(y^2 + h^2).
+///
+/// A single microphone only hears d, so on its own the split between y and h is arbitrary; it
+/// takes another constraint or microphone to tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AltitudeOffset;
+
+impl TrajectoryModel for AltitudeOffset {
+    fn name(&self) -> &'static str {
+        "altitude and offset"
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &ALTITUDE_PARAMETERS
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &ALTITUDE_UNITS
+    }
+
+    //   P = (v tau, y, h)
+    fn state(&self, params: &[f64], t: f64) -> State {
+        let (y, v, t_closest, h) = (params[0], params[1], params[2], params[3]);
+        let tau = t - t_closest;
+        State {
+            position: [v * tau, y, h],
+            velocity: [v, 0.0, 0.0],
+            acceleration: [0.0; 3],
+            d_position: vec![[0.0, 1.0, 0.0], [tau, 0.0, 0.0], [-v, 0.0, 0.0], [0.0, 0.0, 1.0]],
+            d_velocity: vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0; 3], [0.0; 3]],
+        }
+    }
+
+    fn distance(&self, params: &[f64]) -> f64 {
+        params[0].hypot(params[3])
+    }
+
+    // halfway between overhead and on the ground
+    fn guess(&self, estimate: &PassEstimate) -> Vec<f64> {
+        let side = estimate.distance * std::f64::consts::FRAC_1_SQRT_2;
+        vec![side, estimate.speed, estimate.t_closest, side]
+    }
+
+    fn domain(&self, max_speed: f64) -> Vec<(f64, f64)> {
+        let mut domain = pass_domain(max_speed);
+        domain.push((0.0, 100000.0));
+        domain
+    }
+
+    fn search_ranges(&self, max_speed: f64, duration: f64) -> Vec<(f64, f64)> {
+        let mut ranges = pass_search_ranges(max_speed, duration);
+        ranges[0].0 = 0.0;
+        ranges.push((0.0, 2000.0));
+        ranges
+    }
+
+    fn canonical(&self, params: &mut [f64]) {
+        params[0] = params[0].abs();
+        params[3] = params[3].abs();
+    }
+
+    fn reverse(&self, params: &mut [f64]) -> bool {
+        let reversed = params[1] < 0.0;
+        if reversed {
+            params[1] = -params[1];
+        }
+        reversed
+    }
+}
+
+// the free one of ground offset and altitude starts at least this fraction of the miss
+// distance away from zero, where the heard frequency doesn't depend on its sign
+const MIN_COMPONENT_FRACTION: f64 = 0.1;
+
+// the other side of the right triangle with hypotenuse `distance` and side `known`
+pub(crate) fn other_component(distance: f64, known: f64) -> f64 {
+    (distance.powi(2) - known.powi(2)).max((MIN_COMPONENT_FRACTION * distance).powi(2)).sqrt()
+}
+
+/// A straight level line at a known altitude h above the microphone; the ground offset y is
+/// fitted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KnownAltitude {
+    pub altitude: f64,
+}
+
+impl TrajectoryModel for KnownAltitude {
+    fn name(&self) -> &'static str {
+        "known altitude"
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &KNOWN_ALTITUDE_PARAMETERS
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &STRAIGHT_UNITS
+    }
+
+    //   P = (v tau, y, h)
+    fn state(&self, params: &[f64], t: f64) -> State {
+        let (y, v, t_closest) = (params[0], params[1], params[2]);
+        let tau = t - t_closest;
+        State {
+            position: [v * tau, y, self.altitude],
+            velocity: [v, 0.0, 0.0],
+            acceleration: [0.0; 3],
+            d_position: vec![[0.0, 1.0, 0.0], [tau, 0.0, 0.0], [-v, 0.0, 0.0]],
+            d_velocity: vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0; 3]],
+        }
+    }
+
+    fn distance(&self, params: &[f64]) -> f64 {
+        params[0].hypot(self.altitude)
+    }
+
+    fn guess(&self, estimate: &PassEstimate) -> Vec<f64> {
+        let offset = other_component(estimate.distance, self.altitude);
+        vec![offset, estimate.speed, estimate.t_closest]
+    }
+
+    fn domain(&self, max_speed: f64) -> Vec<(f64, f64)> {
+        pass_domain(max_speed)
+    }
+
+    fn search_ranges(&self, max_speed: f64, duration: f64) -> Vec<(f64, f64)> {
+        let mut ranges = pass_search_ranges(max_speed, duration);
+        ranges[0].0 = 0.0;
+        ranges
+    }
+
+    fn canonical(&self, params: &mut [f64]) {
+        params[0] = params[0].abs();
+    }
+
+    fn reverse(&self, params: &mut [f64]) -> bool {
+        Straight.reverse(params)
+    }
+}
+
+/// A straight level line at a known ground offset y from the microphone; the altitude h is
+/// fitted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KnownOffset {
+    pub offset: f64,
+}
+
+impl TrajectoryModel for KnownOffset {
+    fn name(&self) -> &'static str {
+        "known offset"
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &KNOWN_OFFSET_PARAMETERS
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &STRAIGHT_UNITS
+    }
+
+    //   P = (v tau, y, h)
+    fn state(&self, params: &[f64], t: f64) -> State {
+        let (h, v, t_closest) = (params[0], params[1], params[2]);
+        let tau = t - t_closest;
+        State {
+            position: [v * tau, self.offset, h],
+            velocity: [v, 0.0, 0.0],
+            acceleration: [0.0; 3],
+            d_position: vec![[0.0, 0.0, 1.0], [tau, 0.0, 0.0], [-v, 0.0, 0.0]],
+            d_velocity: vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0; 3]],
+        }
+    }
+
+    fn distance(&self, params: &[f64]) -> f64 {
+        params[0].hypot(self.offset)
+    }
+
+    fn guess(&self, estimate: &PassEstimate) -> Vec<f64> {
+        let altitude = other_component(estimate.distance, self.offset);
+        vec![altitude, estimate.speed, estimate.t_closest]
+    }
+
+    fn domain(&self, max_speed: f64) -> Vec<(f64, f64)> {
+        pass_domain(max_speed)
+    }
+
+    fn search_ranges(&self, max_speed: f64, duration: f64) -> Vec<(f64, f64)> {
+        let mut ranges = pass_search_ranges(max_speed, duration);
+        ranges[0].0 = 0.0;
+        ranges
+    }
+
+    // below the microphone sounds the same as above it
+    fn canonical(&self, params: &mut [f64]) {
+        params[0] = params[0].abs();
+    }
+
+    fn reverse(&self, params: &mut [f64]) -> bool {
+        Straight.reverse(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acceleration_keeps_both_ends_subsonic() {
+        // 45 m/s at closest approach, 5 s into a 10 s track: 11 m/s² either way reaches 100 m/s
+        // at one end
+        for (acceleration, end) in [(30.0, 10.0), (-30.0, 0.0)] {
+            let mut params = [60.0, 45.0, 5.0, acceleration];
+            ConstantAcceleration.limit_speed(&mut params, 100.0, 0.0, 10.0);
+            assert!((params[3] - 11.0 * acceleration.signum()).abs() < 1e-9, "{:?}", params);
+            for t in [0.0, 10.0] {
+                let speed = params[1] + params[3] * (t - params[2]);
+                assert!(speed.abs() <= 100.0 + 1e-9, "{} at {}", speed, t);
+            }
+            assert!((params[1] + params[3] * (end - params[2])).abs() > 100.0 - 1e-9);
+        }
+        // within bounds nothing changes
+        let mut params = [60.0, 45.0, 5.0, 2.0];
+        ConstantAcceleration.limit_speed(&mut params, 100.0, 0.0, 10.0);
+        assert_eq!(params[3], 2.0);
+    }
+}
@@ -0,0 +1,212 @@
+use nalgebra::{DMatrix, DVector};
+
+use crate::lma::weight;
+use crate::model::DopplerModel;
+use crate::solver::{DopplerSolver, Observations};
+use crate::spectrum::Hz;
+
+/// How far the fitted parameters can be trusted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterUncertainty {
+    /// Covariance of the parameters from the Jacobian at the optimum, `covariance[i][j]`.
+    pub covariance: Vec<Vec<f64>>,
+    /// Square roots of the covariance diagonal, in the units of the parameters.
+    pub standard_errors: Vec<f64>,
+    /// Correlation between the miss distance and the speed; near ±1 means the track can't tell
+    /// them apart.
+    pub correlation_d_v: f64,
+}
+
+/// Spread of the parameters over bootstrap refits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bootstrap {
+    pub resamples: usize,
+    pub standard_errors: Vec<f64>,
+}
+
+// largest condition number of the information matrix, normalised to unit diagonal, that still
+// gives meaningful error bars; beyond it some combination of parameters is barely constrained
+const MAX_CONDITION: f64 = 1e12;
+
+/// Linearised covariance (JᵀWJ)⁻¹ · s² at `params`.
+///
+/// The frequency uncertainties of the tracker are only relative, so the covariance is scaled by
+/// the reduced chi-square s² = objective / (n - p); the priors on the speed of sound and wind
+/// are added unscaled. `None` if the fit is degenerate: there are no more frames than
+/// parameters, a parameter doesn't change the fit, the normalised information matrix has a
+/// condition number above 10¹², or the inverse has a variance that isn't positive and finite.
+pub fn covariance(model: DopplerModel, observations: &Observations, params: &[f64]) -> Option<ParameterUncertainty> {
+    let n = observations.points.len();
+    let p = params.len();
+    if n <= p {
+        return None;
+    }
+
+    let mut information = DMatrix::<f64>::zeros(p, p);
+    let mut objective = 0.0;
+    for point in &observations.points {
+        let (nu, gradient) = model.gradient(params, observations.tau0, point.time);
+        let w = weight(point);
+        let row = DVector::from_vec(gradient) * w;
+        information += &row * row.transpose();
+        objective += ((nu - point.frequency.0) * w).powi(2);
+    }
+    let reduced_chi_square = objective / (n - p) as f64;
+    information /= reduced_chi_square;
+    // the priors on fitted nuisance parameters are absolute, they don't scale with the residuals
+    for (index, _, derivative) in model.priors(params) {
+        information[(index, index)] += derivative.powi(2);
+    }
+    // scaled to unit diagonal, so that the units of the parameters don't count as ill-conditioning
+    let scale: Vec<f64> = (0..p).map(|i| information[(i, i)]).collect();
+    if scale.iter().any(|s| !(s.is_finite() && *s > 0.0)) {
+        return None;
+    }
+    let normalised = DMatrix::from_fn(p, p, |i, j| information[(i, j)] / (scale[i] * scale[j]).sqrt());
+    let eigenvalues = normalised.symmetric_eigenvalues();
+    let (smallest, largest) = (eigenvalues.min(), eigenvalues.max());
+    if !(smallest > 0.0 && largest / smallest <= MAX_CONDITION) {
+        return None;
+    }
+    let covariance = information.try_inverse()?;
+    let variances: Vec<f64> = (0..p).map(|i| covariance[(i, i)]).collect();
+    if variances.iter().any(|variance| !(variance.is_finite() && *variance > 0.0)) {
+        return None;
+    }
+
+    let standard_errors: Vec<f64> = variances.iter().map(|variance| variance.sqrt()).collect();
+    let (d, v) = (model.distance_index(), model.speed_index());
+    let correlation_d_v = (covariance[(d, v)] / (standard_errors[d] * standard_errors[v])).clamp(-1.0, 1.0);
+    Some(ParameterUncertainty {
+        covariance: (0..p).map(|i| covariance.row(i).iter().copied().collect()).collect(),
+        standard_errors,
+        correlation_d_v,
+    })
+}
+
+// splitmix64, enough for resampling and keeps runs reproducible
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Refits `resamples` bootstrap samples of the frames, starting from `params`, and returns the
+/// standard deviation of the refitted parameters.
+///
+/// Frames keep their times: a frame drawn k times counts k times by having its uncertainty
+/// divided by √k, and a frame not drawn gets no weight at all.
+pub fn bootstrap(
+    model: DopplerModel,
+    observations: &Observations,
+    params: &[f64],
+    solver: &dyn DopplerSolver,
+    resamples: usize,
+    seed: u64,
+) -> Option<Bootstrap> {
+    let n = observations.points.len();
+    if n == 0 || resamples < 2 {
+        return None;
+    }
+    let mut rng = SplitMix(seed);
+    let mut fits = Vec::with_capacity(resamples);
+    for _ in 0..resamples {
+        let mut counts = vec![0usize; n];
+        for _ in 0..n {
+            counts[rng.below(n)] += 1;
+        }
+        let points = observations
+            .points
+            .iter()
+            .zip(&counts)
+            .map(|(point, &k)| {
+                let mut point = *point;
+                point.uncertainty = match k {
+                    0 => Hz(f64::INFINITY),
+                    k => Hz(point.uncertainty.0 / (k as f64).sqrt()),
+                };
+                point
+            })
+            .collect();
+        let sample = Observations {
+            points,
+            tau0: observations.tau0,
+        };
+        let fit = solver.solve(model, &sample, params, None);
+        if fit.report.objective.is_finite() {
+            fits.push(fit.params);
+        }
+    }
+    if fits.len() < 2 {
+        return None;
+    }
+
+    let count = fits.len() as f64;
+    let standard_errors = (0..params.len())
+        .map(|i| {
+            let mean = fits.iter().map(|p| p[i]).sum::<f64>() / count;
+            (fits.iter().map(|p| (p[i] - mean).powi(2)).sum::<f64>() / (count - 1.0)).sqrt()
+        })
+        .collect();
+    Some(Bootstrap {
+        resamples: fits.len(),
+        standard_errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracking::TrackPoint;
+
+    // a straight pass at 50 m and 40 m/s of a 300 Hz source, closest at 5 s, heard with ±0.5 Hz
+    // of alternating error
+    const TRUTH: [f64; 4] = [300.0, 50.0, 40.0, 5.0];
+
+    fn pass(model: DopplerModel, params: &[f64]) -> Observations {
+        let points = (0..100)
+            .map(|k| {
+                let time = 0.1 * k as f64;
+                TrackPoint {
+                    time,
+                    frequency: Hz(model.frequency(params, 0.1, time) + if k % 2 == 0 { 0.5 } else { -0.5 }),
+                    confidence: 1.0,
+                    uncertainty: Hz(1.0),
+                }
+            })
+            .collect();
+        Observations { points, tau0: 0.1 }
+    }
+
+    #[test]
+    fn a_good_fit_has_finite_errors() {
+        let model = DopplerModel::default();
+        let uncertainty = covariance(model, &pass(model, &TRUTH), &TRUTH).unwrap();
+        for (error, value) in uncertainty.standard_errors.iter().zip(TRUTH) {
+            assert!(error.is_finite() && *error > 0.0 && *error < 0.1 * value, "{:?}", uncertainty.standard_errors);
+        }
+        assert!(uncertainty.correlation_d_v.abs() < 1.0);
+    }
+
+    #[test]
+    fn a_degenerate_fit_has_none() {
+        let model = DopplerModel::default();
+        // a source standing still tells nothing about its distance or time of closest approach
+        let still = [300.0, 50.0, 0.0, 5.0];
+        assert_eq!(covariance(model, &pass(model, &still), &still), None);
+        // no more frames than parameters
+        let mut short = pass(model, &TRUTH);
+        short.points.truncate(4);
+        assert_eq!(covariance(model, &short, &TRUTH), None);
+    }
+}